@@ -0,0 +1,868 @@
+use std::{collections::BTreeSet, env, fmt, ops::Range};
+
+use colored::Colorize;
+use serde::{de, Deserialize};
+
+use crate::{
+    config::{
+        Command, Config, EnvOptions, ExitOptions, LogFile, OutputMode, Success, TaskOptions,
+        ENV_KEYS, EXIT_KEYS, PROFILE_KEYS, READY_KEYS, ROOT_KEYS, TASK_KEYS,
+    },
+    discover::{ConfigFile, SourceFile},
+    events::{self, Event, Level},
+    graph::TaskGraph,
+    output,
+    prefix::Template,
+    watch,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the config, pointing at where it is in the file if it could be found.
+pub struct Diagnostic {
+    severity: Severity,
+    message: String,
+    location: Option<Location>,
+    help: Option<String>,
+}
+
+struct Location {
+    file: String,
+    line: usize,
+    column: usize,
+    text: String,
+    /// Columns of `text` to underline.
+    underline: Range<usize>,
+}
+
+/// What a diagnostic points at, for a dotted key in the config.
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    Key,
+    Value,
+    /// A string in an array value.
+    Element(&'a str),
+}
+
+/// Checks the config for unknown keys, invalid values, references to unknown tasks and dependency
+/// cycles, along with the task and profile names and success condition given on the command line.
+pub fn check(
+    config_file: &ConfigFile,
+    tasks: &[String],
+    profile: Option<&str>,
+    success: Option<&Success>,
+) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        files: config_file
+            .files
+            .iter()
+            .map(|file| (file, toml::from_str(&file.contents).ok()))
+            .collect(),
+        diagnostics: Vec::new(),
+    };
+    let table = &config_file.table;
+
+    checker.root(table);
+    let task_names = checker.tasks(table);
+    checker.profiles(table, &task_names);
+    checker.selection(table, &task_names, tasks, profile);
+    checker.success(table, &task_names, success);
+    if !checker.has_errors() {
+        checker.graph(table);
+    }
+
+    checker.diagnostics
+}
+
+/// Prints each diagnostic, or emits it as a log event when events are enabled, returning the
+/// number of errors.
+pub fn report(diagnostics: &[Diagnostic]) -> usize {
+    for diagnostic in diagnostics {
+        if events::enabled() {
+            events::emit(Event::Log {
+                level: match diagnostic.severity {
+                    Severity::Error => Level::Error,
+                    Severity::Warning => Level::Warn,
+                },
+                message: output::strip_ansi(&Body(diagnostic).to_string()),
+            });
+        } else {
+            println!("{diagnostic}");
+        }
+    }
+    diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count()
+}
+
+struct Checker<'a> {
+    /// Files in the order they were merged, along with their keys if they could be parsed.
+    files: Vec<(&'a SourceFile, Option<Node>)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn root(&mut self, table: &toml::Table) {
+        for (key, value) in table {
+            if !ROOT_KEYS.contains(&key.as_str()) {
+                self.unknown_key(&[key], ROOT_KEYS, "in config");
+            } else if ENV_KEYS.contains(&key.as_str()) {
+                let env = toml::Table::from_iter([(key.clone(), value.clone())]);
+                if let Err(err) = EnvOptions::deserialize(env) {
+                    self.invalid(&[key], err.message());
+                }
+            } else if EXIT_KEYS.contains(&key.as_str()) {
+                let exit = toml::Table::from_iter([(key.clone(), value.clone())]);
+                if let Err(err) = ExitOptions::deserialize(exit) {
+                    self.invalid(&[key], err.message());
+                }
+            } else if key == "output" {
+                if let Err(err) = OutputMode::deserialize(value.clone()) {
+                    self.invalid(&[key], err.message());
+                }
+            } else if key == "log_file" {
+                if let Err(err) = LogFile::deserialize(value.clone()) {
+                    self.invalid(&[key], err.message());
+                }
+            } else if key == "prefix" {
+                if let Err(err) = Template::deserialize(value.clone()) {
+                    self.invalid(&[key], err.message());
+                }
+            }
+        }
+    }
+
+    fn tasks(&mut self, table: &toml::Table) -> BTreeSet<String> {
+        let Some(tasks) = self.table(table, &["tasks"]) else {
+            return BTreeSet::new();
+        };
+
+        for name in tasks.keys() {
+            let path = ["tasks", name.as_str()];
+            let Some(task) = self.table(tasks, &path) else {
+                continue;
+            };
+
+            let errors = self.diagnostics.len();
+            self.task_options(&path, task, &format!("in task {name}"));
+            if self.diagnostics.len() > errors {
+                continue;
+            }
+            if let Err(err) = TaskOptions::deserialize(task.clone()) {
+                self.error(
+                    format!("invalid task {name}: {}", err.message()),
+                    self.locate(&path, Target::Key),
+                    None,
+                );
+                continue;
+            }
+
+            if let Some(command) = task.get("command") {
+                if Command::deserialize(command.clone()).is_ok_and(|command| command.is_empty()) {
+                    self.push(Diagnostic {
+                        severity: Severity::Warning,
+                        message: format!("task {name} has no command configured"),
+                        location: self.locate(&[&path[..], &["command"]].concat(), Target::Value),
+                        help: None,
+                    });
+                }
+            }
+        }
+
+        let names: BTreeSet<_> = tasks.keys().cloned().collect();
+        for (name, task) in tasks {
+            let Some(depends_on) = task.get("depends_on").and_then(toml::Value::as_array) else {
+                continue;
+            };
+            for dependency in depends_on.iter().filter_map(toml::Value::as_str) {
+                let path = ["tasks", name.as_str(), "depends_on"];
+                if dependency == name {
+                    self.error(
+                        format!("task {name} depends on itself"),
+                        self.locate(&path, Target::Element(dependency)),
+                        None,
+                    );
+                } else if !names.contains(dependency) {
+                    self.error(
+                        format!("task {name} depends on unknown task {dependency}"),
+                        self.locate(&path, Target::Element(dependency)),
+                        did_you_mean(
+                            dependency,
+                            names.iter().map(String::as_str).filter(|n| n != name),
+                        ),
+                    );
+                }
+            }
+        }
+
+        names
+    }
+
+    fn profiles(&mut self, table: &toml::Table, task_names: &BTreeSet<String>) {
+        let Some(profiles) = self.table(table, &["profiles"]) else {
+            return;
+        };
+
+        let profile_keys: Vec<_> = PROFILE_KEYS.iter().chain(TASK_KEYS).copied().collect();
+        for name in profiles.keys() {
+            let Some(profile) = self.table(profiles, &["profiles", name]) else {
+                continue;
+            };
+            let context = format!("in profile {name}");
+            for (key, value) in profile {
+                let path = ["profiles", name.as_str(), key.as_str()];
+                match key.as_str() {
+                    "tasks" => match Vec::<String>::deserialize(value.clone()) {
+                        Ok(tasks) => {
+                            for task in tasks.iter().filter(|task| !task_names.contains(*task)) {
+                                self.error(
+                                    format!("profile {name} refers to unknown task {task}"),
+                                    self.locate(&path, Target::Element(task)),
+                                    did_you_mean(task, task_names.iter().map(String::as_str)),
+                                );
+                            }
+                        }
+                        Err(err) => self.invalid(&path, err.message()),
+                    },
+                    "overrides" => {
+                        let Some(overrides) = self.table(profile, &path) else {
+                            continue;
+                        };
+                        for task in overrides.keys() {
+                            let path = [&path[..], &[task.as_str()]].concat();
+                            if !task_names.contains(task) {
+                                self.error(
+                                    format!("profile {name} refers to unknown task {task}"),
+                                    self.locate(&path, Target::Key),
+                                    did_you_mean(task, task_names.iter().map(String::as_str)),
+                                );
+                            } else if let Some(options) = self.table(overrides, &path) {
+                                self.task_options(&path, options, &context);
+                            }
+                        }
+                    }
+                    _ if !profile_keys.contains(&key.as_str()) => {
+                        self.unknown_key(&path, &profile_keys, &context);
+                    }
+                    _ => self.task_option(&path, value, &context),
+                }
+            }
+        }
+    }
+
+    fn selection(
+        &mut self,
+        table: &toml::Table,
+        task_names: &BTreeSet<String>,
+        tasks: &[String],
+        profile: Option<&str>,
+    ) {
+        for task in tasks.iter().filter(|task| !task_names.contains(*task)) {
+            self.error(
+                format!("unknown task {task}"),
+                None,
+                did_you_mean(task, task_names.iter().map(String::as_str)),
+            );
+        }
+
+        let profiles = table.get("profiles").and_then(toml::Value::as_table);
+        if let Some(profile) = profile {
+            if !profiles.is_some_and(|profiles| profiles.contains_key(profile)) {
+                self.error(
+                    format!("unknown profile {profile}"),
+                    None,
+                    did_you_mean(
+                        profile,
+                        profiles
+                            .into_iter()
+                            .flat_map(|p| p.keys())
+                            .map(String::as_str),
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Checks that a task named as the success condition exists, preferring the command line.
+    fn success(
+        &mut self,
+        table: &toml::Table,
+        task_names: &BTreeSet<String>,
+        success: Option<&Success>,
+    ) {
+        let (name, location) = match success {
+            Some(Success::Task(name)) => (name.as_str(), None),
+            Some(_) => return,
+            None => match table.get("success").and_then(toml::Value::as_str) {
+                Some(name) => match name.parse() {
+                    Ok(Success::Task(_)) => (name, self.locate(&["success"], Target::Value)),
+                    _ => return,
+                },
+                None => return,
+            },
+        };
+        if !task_names.contains(name) {
+            self.error(
+                format!("unknown task {name} in success condition"),
+                location,
+                did_you_mean(
+                    name,
+                    ["all", "first", "last"]
+                        .into_iter()
+                        .chain(task_names.iter().map(String::as_str)),
+                )
+                .or_else(|| Some("expected all, first, last or a task name".to_string())),
+            );
+        }
+    }
+
+    fn graph(&mut self, table: &toml::Table) {
+        let result = Config::deserialize(table.clone())
+            .map_err(anyhow::Error::from)
+            .and_then(|config| TaskGraph::new(&config.tasks));
+        if let Err(err) = result {
+            self.error(err.to_string(), None, None);
+        }
+    }
+
+    /// Checks the keys and values of a task's options, or a profile's overrides of them.
+    fn task_options(&mut self, path: &[&str], options: &toml::Table, context: &str) {
+        for (key, value) in options {
+            let path = [path, &[key.as_str()]].concat();
+            if TASK_KEYS.contains(&key.as_str()) {
+                self.task_option(&path, value, context);
+            } else {
+                self.unknown_key(&path, TASK_KEYS, context);
+            }
+        }
+    }
+
+    fn task_option(&mut self, path: &[&str], value: &toml::Value, context: &str) {
+        if let (toml::Value::Table(ready), "ready") = (value, path[path.len() - 1]) {
+            let errors = self.diagnostics.len();
+            for key in ready
+                .keys()
+                .filter(|key| !READY_KEYS.contains(&key.as_str()))
+            {
+                let path = [path, &[key.as_str()]].concat();
+                self.unknown_key(&path, READY_KEYS, &format!("in ready probe {context}"));
+            }
+            if self.diagnostics.len() > errors {
+                return;
+            }
+        }
+        if path[path.len() - 1] == "output" && value.as_str() == Some("json") {
+            self.error(
+                format!("invalid output {context}: json output can only be set for all tasks"),
+                self.locate(path, Target::Value),
+                Some("set it at the top of tasks.toml, or pass --output json".to_string()),
+            );
+            return;
+        }
+        let errors = self.diagnostics.len();
+        self.task_value(path, value, context);
+        if self.diagnostics.len() == errors {
+            self.patterns(path, value);
+        }
+    }
+
+    /// Checks each `watch` glob and `ignore` pattern.
+    fn patterns(&mut self, path: &[&str], value: &toml::Value) {
+        let key = path[path.len() - 1];
+        let Some(patterns) = value.as_array() else {
+            return;
+        };
+        for pattern in patterns.iter().filter_map(toml::Value::as_str) {
+            let err = match key {
+                "watch" => watch::check_watch(pattern).err().map(|err| err.to_string()),
+                "ignore" => watch::check_ignore(pattern)
+                    .err()
+                    .map(|err| err.to_string()),
+                _ => None,
+            };
+            if let Some(err) = err {
+                self.error(
+                    format!("invalid {key} pattern: {err}"),
+                    self.locate(path, Target::Element(pattern)),
+                    None,
+                );
+            }
+        }
+    }
+
+    /// Checks a single task option, by parsing it in an otherwise minimal task.
+    fn task_value(&mut self, path: &[&str], value: &toml::Value, context: &str) {
+        let key = path[path.len() - 1];
+        let mut task = toml::Table::new();
+        if key != "command" {
+            task.insert("command".to_string(), toml::Value::String(String::new()));
+        }
+        if matches!(key, "release" | "features" | "rebuild") {
+            task.insert("cargo".to_string(), toml::Value::Boolean(true));
+        }
+        task.insert(key.to_string(), value.clone());
+
+        if let Err(err) = TaskOptions::deserialize(task) {
+            self.error(
+                format!("invalid {key} {context}: {}", err.message()),
+                self.locate(path, Target::Value),
+                None,
+            );
+        }
+    }
+
+    /// Returns the table at the end of `path` within `table`, reporting an error if it's
+    /// something else.
+    fn table<'t>(&mut self, table: &'t toml::Table, path: &[&str]) -> Option<&'t toml::Table> {
+        match table.get(path[path.len() - 1])? {
+            toml::Value::Table(table) => Some(table),
+            value => {
+                self.error(
+                    format!(
+                        "{} must be a table, found {}",
+                        path.join("."),
+                        value.type_str()
+                    ),
+                    self.locate(path, Target::Value),
+                    None,
+                );
+                None
+            }
+        }
+    }
+
+    fn unknown_key(&mut self, path: &[&str], expected: &[&str], context: &str) {
+        let key = path[path.len() - 1];
+        self.error(
+            format!("unknown key {key} {context}"),
+            self.locate(path, Target::Key),
+            did_you_mean(key, expected.iter().copied()),
+        );
+    }
+
+    fn invalid(&mut self, path: &[&str], message: &str) {
+        let key = path[path.len() - 1];
+        self.error(
+            format!("invalid {key}: {message}"),
+            self.locate(path, Target::Value),
+            None,
+        );
+    }
+
+    fn error(&mut self, message: String, location: Option<Location>, help: Option<String>) {
+        self.push(Diagnostic {
+            severity: Severity::Error,
+            message,
+            location,
+            help,
+        });
+    }
+
+    fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    /// Finds the key in the last merged file which sets it.
+    fn locate(&self, path: &[&str], target: Target) -> Option<Location> {
+        self.files.iter().rev().find_map(|(file, node)| {
+            let mut node = node.as_ref()?;
+            let mut key_span = None;
+            for key in file.prefix.iter().copied().chain(path.iter().copied()) {
+                let (span, value) = node.get(key)?;
+                key_span = Some(span);
+                node = value;
+            }
+            let key_span = key_span?;
+
+            let span = match target {
+                Target::Key => key_span.clone(),
+                Target::Value => value_span(&file.contents, key_span.end)?,
+                Target::Element(element) => {
+                    let value = value_span(&file.contents, key_span.end)?;
+                    element_span(&file.contents, value.start, element).unwrap_or(value)
+                }
+            };
+            Some(Location::new(file, span))
+        })
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = match self.severity {
+            Severity::Error => "[error]:".bold().red(),
+            Severity::Warning => "[warn]:".bold().yellow(),
+        };
+        write!(f, "{tag} {}", Body(self))
+    }
+}
+
+/// A diagnostic without its severity tag.
+struct Body<'a>(&'a Diagnostic);
+
+impl fmt::Display for Body<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Body(diagnostic) = self;
+        write!(f, "{}", diagnostic.message)?;
+
+        let gutter = diagnostic
+            .location
+            .as_ref()
+            .map(|location| location.line.to_string().len())
+            .unwrap_or(1);
+        let pad = " ".repeat(gutter);
+        if let Some(location) = &diagnostic.location {
+            let Location {
+                file,
+                line,
+                column,
+                text,
+                underline,
+            } = location;
+            let marker = "^".repeat(underline.len().max(1));
+            let marker = match diagnostic.severity {
+                Severity::Error => marker.bold().red(),
+                Severity::Warning => marker.bold().yellow(),
+            };
+            write!(
+                f,
+                "\n{pad}{} {file}:{line}:{column}\n{pad} {}\n{} {text}\n{pad} {} {}{marker}",
+                "-->".bold().blue(),
+                "|".bold().blue(),
+                format!("{line:>gutter$} |").bold().blue(),
+                "|".bold().blue(),
+                " ".repeat(underline.start),
+            )?;
+        }
+        if let Some(help) = &diagnostic.help {
+            write!(f, "\n{pad} {} help: {help}", "=".bold().blue())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Location {
+    fn new(file: &SourceFile, span: Range<usize>) -> Self {
+        let contents = &file.contents;
+        let line_start = contents[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = contents[span.start..]
+            .find('\n')
+            .map_or(contents.len(), |i| span.start + i);
+        let text = contents[line_start..line_end].trim_end_matches('\r');
+
+        let start = contents[line_start..span.start].chars().count();
+        let end = start + contents[span.start..span.end.min(line_end)].chars().count();
+
+        let path = env::current_dir()
+            .ok()
+            .and_then(|dir| file.path.strip_prefix(dir).ok())
+            .unwrap_or(&file.path);
+
+        Location {
+            file: path.display().to_string(),
+            line: contents[..span.start].matches('\n').count() + 1,
+            column: start + 1,
+            text: text.to_string(),
+            underline: start..end,
+        }
+    }
+}
+
+/// Suggests the closest of `candidates`, allowing about one typo for every three characters.
+pub fn did_you_mean<'a>(
+    input: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    let max_distance = (input.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (strsim::damerau_levenshtein(input, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!("did you mean {candidate}?"))
+}
+
+/// Span of the value following a key, ending at the end of the line for values which continue
+/// across lines.
+fn value_span(contents: &str, key_end: usize) -> Option<Range<usize>> {
+    let rest = &contents[key_end..];
+    let after_eq = rest.trim_start_matches([' ', '\t']).strip_prefix('=')?;
+    let start = contents.len() - after_eq.trim_start_matches([' ', '\t']).len();
+    let line = &contents[start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+
+    let len = match line.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let mut escaped = false;
+            line.char_indices()
+                .skip(1)
+                .find(|&(_, c)| {
+                    let end = c == quote && !escaped;
+                    escaped = quote == '"' && c == '\\' && !escaped;
+                    end
+                })
+                .map_or(line.len(), |(i, c)| i + c.len_utf8())
+        }
+        '[' | '{' => {
+            let mut depth = 0;
+            line.char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '[' | '{' => depth += 1,
+                        ']' | '}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .map_or(line.trim_end().len(), |(i, _)| i + 1)
+        }
+        _ => line
+            .find(|c: char| c.is_whitespace() || c == ',' || c == '}' || c == '#')
+            .unwrap_or(line.len()),
+    };
+
+    Some(start..start + len)
+}
+
+/// Span of a quoted string within an array starting at `start`.
+fn element_span(contents: &str, start: usize, element: &str) -> Option<Range<usize>> {
+    let rest = &contents[start..];
+    let end = rest.find(']').unwrap_or(rest.len());
+    ['"', '\''].into_iter().find_map(|quote| {
+        let quoted = format!("{quote}{element}{quote}");
+        let i = rest[..end].find(&quoted)?;
+        Some(start + i..start + i + quoted.len())
+    })
+}
+
+/// Keys of a toml file along with their spans, used to point diagnostics at the right place.
+///
+/// Only keys are spanned, since tables made implicitly by headers such as `[tasks.server]` have no
+/// span of their own.
+enum Node {
+    Table(Vec<(toml::Spanned<String>, Node)>),
+    Array,
+    Value,
+}
+
+impl Node {
+    fn get(&self, key: &str) -> Option<(Range<usize>, &Node)> {
+        match self {
+            Node::Table(entries) => entries
+                .iter()
+                .find(|(k, _)| k.get_ref() == key)
+                .map(|(k, node)| (k.span(), node)),
+            Node::Array | Node::Value => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct NodeVisitor;
+
+        impl<'de> de::Visitor<'de> for NodeVisitor {
+            type Value = Node;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a toml value")
+            }
+
+            fn visit_bool<E>(self, _: bool) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_i64<E>(self, _: i64) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_u64<E>(self, _: u64) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_f64<E>(self, _: f64) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_str<E>(self, _: &str) -> Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Node, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                while seq.next_element::<de::IgnoredAny>()?.is_some() {}
+                Ok(Node::Array)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Node, A::Error>
+            where
+                A: de::MapAccess<'de>,
+            {
+                let mut entries = Vec::new();
+                while let Some(key) = map.next_key()? {
+                    entries.push((key, map.next_value()?));
+                }
+                Ok(Node::Table(entries))
+            }
+        }
+
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::layer::Sources;
+
+    use super::*;
+
+    /// Checks a `tasks.toml`, or the part of a `Cargo.toml` under `prefix`, returning each
+    /// diagnostic's message, line and the text its carets are under.
+    fn underlined(contents: &str, prefix: &[&'static str]) -> Vec<(String, usize, String)> {
+        let mut table: toml::Table = toml::from_str(contents).unwrap();
+        for key in prefix {
+            table = table[*key].as_table().unwrap().clone();
+        }
+        let config_file = ConfigFile {
+            path: PathBuf::from("tasks.toml"),
+            table,
+            sources: Sources::default(),
+            files: vec![SourceFile {
+                path: PathBuf::from("tasks.toml"),
+                contents: contents.to_string(),
+                prefix: prefix.to_vec(),
+            }],
+        };
+
+        check(&config_file, &[], None, None)
+            .into_iter()
+            .map(|diagnostic| {
+                let location = diagnostic.location.expect("diagnostic has a location");
+                let text = location
+                    .text
+                    .chars()
+                    .skip(location.underline.start)
+                    .take(location.underline.len())
+                    .collect();
+                (diagnostic.message, location.line, text)
+            })
+            .collect()
+    }
+
+    fn span(contents: &str, span: Option<Range<usize>>) -> &str {
+        &contents[span.expect("span is found")]
+    }
+
+    #[test]
+    fn value_span_covers_whole_value() {
+        fn value(contents: &str) -> &str {
+            span(contents, value_span(contents, 1))
+        }
+
+        assert_eq!(value("a = \"x \\\" y\" # note"), "\"x \\\" y\"");
+        assert_eq!(value("a = 'x \\' # note"), "'x \\'");
+        assert_eq!(value("a = 42 # note"), "42");
+        assert_eq!(value("a=true\nb = 1"), "true");
+        assert_eq!(value("a = { b = [1, 2] }, c"), "{ b = [1, 2] }");
+        assert_eq!(value("a = [\n  1,\n]"), "[");
+        assert_eq!(value_span("a b = 1", 1), None);
+    }
+
+    #[test]
+    fn element_span_finds_quoted_element() {
+        let contents = r#"a = ["dbx", 'db', "web"] # "api""#;
+        let element = |element| span(contents, element_span(contents, 4, element));
+
+        assert_eq!(element("db"), "'db'");
+        assert_eq!(element("web"), "\"web\"");
+        assert_eq!(element_span(contents, 4, "api"), None);
+    }
+
+    #[test]
+    fn points_at_unknown_key() {
+        let contents = "[tasks.web]\ncommand = \"serve\"\ncomand = \"serve\"\n";
+
+        assert_eq!(
+            underlined(contents, &[]),
+            [(
+                "unknown key comand in task web".to_string(),
+                3,
+                "comand".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn points_at_invalid_value() {
+        let contents = "[tasks.web]\ncommand = \"serve\"\nrestart = \"sometimes\" # never\n";
+        let diagnostics = underlined(contents, &[]);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].1, 3);
+        assert_eq!(diagnostics[0].2, "\"sometimes\"");
+    }
+
+    #[test]
+    fn points_at_unknown_dependency() {
+        let contents = r#"
+[tasks.db]
+command = "postgres"
+
+[tasks.web]
+command = "serve"
+depends_on = [
+    "db",
+    "dbx",
+]
+"#;
+
+        assert_eq!(
+            underlined(contents, &[]),
+            [(
+                "task web depends on unknown task dbx".to_string(),
+                9,
+                "\"dbx\"".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn points_into_cargo_metadata() {
+        let contents = r#"
+[package]
+name = "app"
+
+[package.metadata.concurrently.tasks.web]
+command = "serve"
+depends_on = ["web"]
+"#;
+
+        assert_eq!(
+            underlined(contents, &["package", "metadata", "concurrently"]),
+            [(
+                "task web depends on itself".to_string(),
+                7,
+                "\"web\"".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    io::{self, BufRead},
+    thread,
+};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{check::did_you_mean, log::warn, runner::Control};
+
+const USAGE: &str = "expected rs <task>, stop <task>, start <task>, ls or q";
+
+/// Reads commands typed on stdin a line at a time, sending them to the runner. Only started while
+/// concurrently is in the foreground of a terminal.
+///
+/// Unknown commands and tasks are reported and otherwise ignored. Reading stops once stdin is
+/// closed.
+pub fn read(names: Vec<String>, controls: UnboundedSender<Control>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let control = match parse(&line, &names) {
+                Ok(Some(control)) => control,
+                Ok(None) => continue,
+                Err(err) => {
+                    warn(err);
+                    continue;
+                }
+            };
+            if controls.send(control).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn parse(line: &str, names: &[String]) -> Result<Option<Control>, String> {
+    let words: Vec<_> = line.split_whitespace().collect();
+    let control = match words[..] {
+        [] => return Ok(None),
+        ["q" | "quit"] => Control::Quit,
+        ["ls"] => Control::List,
+        ["rs" | "restart", name] => Control::Restart(task(name, names)?),
+        ["stop", name] => Control::Stop(task(name, names)?),
+        ["start", name] => Control::Start(task(name, names)?),
+        _ => return Err(format!("unknown command {line:?}, {USAGE}")),
+    };
+
+    Ok(Some(control))
+}
+
+fn task(name: &str, names: &[String]) -> Result<usize, String> {
+    names.iter().position(|n| n == name).ok_or_else(|| {
+        let mut err = format!("unknown task {name}");
+        if let Some(suggestion) = did_you_mean(name, names.iter().map(String::as_str)) {
+            err.push_str(", ");
+            err.push_str(&suggestion);
+        }
+        err
+    })
+}
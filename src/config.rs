@@ -1,12 +1,160 @@
-use std::{collections::HashMap, fmt, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
 
+use nix::sys::signal::Signal;
+use regex::Regex;
 use serde::{de, Deserialize};
 
+use crate::prefix::Template;
+
+/// Keys allowed at the top of the config, including `extends` and `include` which are handled
+/// while loading it.
+pub const ROOT_KEYS: &[&str] = &[
+    "tasks",
+    "profiles",
+    "extends",
+    "include",
+    "env",
+    "env_file",
+    "clear_env",
+    "env_allow",
+    "kill_others",
+    "kill_others_on_fail",
+    "success",
+    "exit_code",
+    "output",
+    "prefix",
+    "log_file",
+];
+
+/// Keys of [`ExitOptions`].
+pub const EXIT_KEYS: &[&str] = &["kill_others", "kill_others_on_fail", "success", "exit_code"];
+
+/// Keys of [`EnvOptions`].
+pub const ENV_KEYS: &[&str] = &["env", "env_file", "clear_env", "env_allow"];
+
+/// Keys of [`TaskOptions`], including those of each task type.
+pub const TASK_KEYS: &[&str] = &[
+    "command",
+    "cargo",
+    "release",
+    "features",
+    "rebuild",
+    "prepare",
+    "env",
+    "env_file",
+    "clear_env",
+    "env_allow",
+    "cwd",
+    "delay",
+    "retries",
+    "depends_on",
+    "ready",
+    "restart",
+    "backoff",
+    "stop_signal",
+    "stop_timeout",
+    "watch",
+    "ignore",
+    "watch_gitignore",
+    "stdin",
+    "output",
+    "log_file",
+];
+
+/// Keys of [`Profile`], which also accepts any of [`TASK_KEYS`].
+pub const PROFILE_KEYS: &[&str] = &["tasks", "overrides"];
+
+/// Keys of [`ReadyOptions`], including each kind of probe.
+pub const READY_KEYS: &[&str] = &[
+    "output", "tcp", "http", "file", "command", "interval", "timeout",
+];
+
 #[derive(Debug, Default, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub tasks: HashMap<String, TaskOptions>,
-    pub env: Option<String>,
+    pub profiles: HashMap<String, Profile>,
+    #[serde(flatten)]
+    pub env: EnvOptions,
+    #[serde(flatten)]
+    pub exit: ExitOptions,
+    /// How task output is printed, unless a task sets its own.
+    pub output: OutputMode,
+    /// What comes before each line of output, such as `[{time}] {name}`.
+    pub prefix: Template,
+    /// Where each task's output is saved, unless a task sets its own.
+    pub log_file: Option<LogFile>,
+}
+
+/// When the whole run stops, and whether it counts as a success.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ExitOptions {
+    /// Stop all other tasks once any task exits for good.
+    pub kill_others: bool,
+    /// Stop all other tasks once any task fails for good.
+    pub kill_others_on_fail: bool,
+    pub success: Success,
+    pub exit_code: ExitCode,
+}
+
+/// Which tasks must exit successfully for the run to succeed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Success {
+    /// Every task, where tasks stopped by concurrently don't count as failures.
+    #[default]
+    All,
+    /// The first task to exit by itself.
+    First,
+    /// The last task to exit by itself.
+    Last,
+    /// The named task.
+    Task(String),
+}
+
+/// Which failed task the exit code is taken from when the run fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExitCode {
+    /// The first task to fail.
+    #[default]
+    First,
+    /// The last task to fail.
+    Last,
+    /// The highest exit code of the failed tasks.
+    Max,
+}
+
+impl FromStr for Success {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "all" => Success::All,
+            "first" => Success::First,
+            "last" => Success::Last,
+            task => Success::Task(task.to_string()),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Tasks to run, or every task when empty.
+    pub tasks: Vec<String>,
+    /// Task options overriding those of individual tasks.
+    pub overrides: HashMap<String, toml::Table>,
+    /// Task options overriding those of every task.
+    #[serde(flatten)]
+    pub options: toml::Table,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -15,11 +163,216 @@ pub struct TaskOptions {
     pub task_options: TaskTypeOptions,
     #[serde(default)]
     pub prepare: Option<Command>,
-    // pub env_file: Option<String>, // Allow hard-coded env, or env file
+    #[serde(flatten)]
+    pub env: EnvOptions,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
     #[serde(default, with = "humantime_serde")]
     pub delay: Option<Duration>,
     #[serde(default)]
     pub retries: usize,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    pub ready: Option<ReadyOptions>,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default)]
+    pub backoff: Backoff,
+    #[serde(
+        default = "TaskOptions::default_stop_signal",
+        deserialize_with = "deserialize_signal"
+    )]
+    pub stop_signal: Signal,
+    #[serde(
+        default = "TaskOptions::default_stop_timeout",
+        with = "humantime_serde"
+    )]
+    pub stop_timeout: Duration,
+    /// Globs of files which restart the task when changed, relative to its working directory.
+    #[serde(default)]
+    pub watch: Vec<String>,
+    /// Gitignore-style patterns of files to leave out of `watch`.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    #[serde(default = "TaskOptions::default_watch_gitignore")]
+    pub watch_gitignore: bool,
+    #[serde(default)]
+    pub stdin: Stdin,
+    #[serde(default)]
+    pub output: Option<OutputMode>,
+    #[serde(default)]
+    pub log_file: Option<LogFile>,
+}
+
+impl TaskOptions {
+    fn default_stop_signal() -> Signal {
+        Signal::SIGTERM
+    }
+
+    fn default_stop_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn default_watch_gitignore() -> bool {
+        true
+    }
+}
+
+/// When a task is restarted after it exits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Never restart.
+    No,
+    /// Restart after failing, up to `retries` times.
+    #[default]
+    OnFailure,
+    /// Restart whenever it exits.
+    Always,
+    /// Restart whenever it exits like [`RestartPolicy::Always`], but once stopped through
+    /// concurrently, stay stopped even when watched files change.
+    UnlessStopped,
+}
+
+/// Wait between restarts, growing by `factor` with each restart up to `max`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Backoff {
+    #[serde(with = "humantime_serde")]
+    pub initial: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max: Duration,
+    pub factor: f64,
+    /// Fraction of the wait to randomly add or subtract, so that tasks failing together don't
+    /// restart in lockstep.
+    pub jitter: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+            factor: 2.0,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl Backoff {
+    /// Wait before the given restart in a row, counting from zero.
+    pub fn delay(&self, restart: usize) -> Duration {
+        let exponent = i32::try_from(restart).unwrap_or(i32::MAX);
+        let secs = self.initial.as_secs_f64() * self.factor.powi(exponent);
+        let jitter = self.jitter.clamp(0.0, 1.0) * (fastrand::f64() * 2.0 - 1.0);
+        Duration::try_from_secs_f64(secs * (1.0 + jitter))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+/// Environment variables for tasks, configured both globally and per task.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EnvOptions {
+    pub env: Option<Env>,
+    #[serde(deserialize_with = "deserialize_one_or_many")]
+    pub env_file: Vec<PathBuf>,
+    pub clear_env: Option<bool>,
+    pub env_allow: Option<Vec<String>>,
+}
+
+/// Where a task's standard input comes from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Stdin {
+    /// Nothing, so reading gets end of file straight away.
+    #[default]
+    Null,
+    /// Keyboard input, while the task has focus.
+    Inherit,
+    /// A file, relative to `tasks.toml`.
+    File(PathBuf),
+}
+
+/// How a task's output is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputMode {
+    /// Each line as it arrives, after the task's tag.
+    #[default]
+    Prefixed,
+    /// All output of each run together once it exits, after the task's tag.
+    Grouped,
+    /// Each line as it arrives, unchanged.
+    Raw,
+    /// Newline-delimited JSON events for the whole run instead of text, which can only be set for
+    /// all tasks.
+    Json,
+}
+
+/// Where a task's output is saved, given as just the path or as a table of options.
+#[derive(Clone, Debug)]
+pub struct LogFile {
+    /// Relative to `tasks.toml`, where `{name}` is replaced with the task's name.
+    pub path: String,
+    /// Remove colors and other escape sequences from each line.
+    pub strip_ansi: bool,
+    /// Start each line with the local date and time it was printed at.
+    pub timestamps: bool,
+    /// Size in bytes the file can grow to before it's rotated.
+    pub max_size: Option<u64>,
+    /// Rotated files kept alongside the current one, where `<path>.1` is the newest.
+    pub max_files: usize,
+}
+
+impl LogFile {
+    fn default_max_files() -> usize {
+        5
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Env {
+    /// Path to an env file.
+    File(PathBuf),
+    Vars(BTreeMap<String, String>),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReadyOptions {
+    #[serde(flatten)]
+    pub probe: Probe,
+    #[serde(default = "ReadyOptions::default_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(default = "ReadyOptions::default_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl ReadyOptions {
+    fn default_interval() -> Duration {
+        Duration::from_millis(500)
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(30)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Probe {
+    /// A line of output matching this pattern.
+    #[serde(deserialize_with = "deserialize_regex")]
+    Output(Regex),
+    /// An address accepting TCP connections, such as `localhost:5432`.
+    Tcp(String),
+    /// A `http://` url responding with a 2xx status.
+    #[serde(deserialize_with = "deserialize_http_url")]
+    Http(String),
+    /// A file existing.
+    File(PathBuf),
+    /// A command exiting successfully.
+    Command(Command),
 }
 
 #[derive(Clone, Debug)]
@@ -33,6 +386,8 @@ pub enum TaskTypeOptions {
 pub struct CargoTaskOptions {
     pub release: bool,
     pub features: Vec<String>,
+    /// Rebuild and restart when the sources of the package or its path dependencies change.
+    pub rebuild: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -76,11 +431,15 @@ impl<'de> Deserialize<'de> for TaskTypeOptions {
                     .map_err(|err| serde::de::Error::custom(err.message()))?;
                 Ok(TaskTypeOptions::Cargo(opts))
             }
-            _ => {
+            None | Some(toml::Value::Boolean(false)) => {
                 let opts = ShellTaskOptions::deserialize(table)
                     .map_err(|err| serde::de::Error::custom(err.message()))?;
                 Ok(TaskTypeOptions::Shell(opts))
             }
+            Some(value) => Err(de::Error::custom(format!(
+                "invalid type: {}, expected a boolean",
+                value.type_str()
+            ))),
         }
     }
 }
@@ -147,3 +506,262 @@ impl<'de> Deserialize<'de> for Command {
         }
     }
 }
+
+impl<'de> Deserialize<'de> for Success {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(s.parse().unwrap_or_else(|err| match err {}))
+    }
+}
+
+impl<'de> Deserialize<'de> for Stdin {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "null" => Ok(Stdin::Null),
+            "inherit" => Ok(Stdin::Inherit),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Stdin::File(PathBuf::from(path))),
+                _ => Err(de::Error::invalid_value(
+                    de::Unexpected::Str(&s),
+                    &"inherit, null or file:<path>",
+                )),
+            },
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LogFile {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(remote = "LogFile", deny_unknown_fields)]
+        struct Options {
+            path: String,
+            #[serde(default)]
+            strip_ansi: bool,
+            #[serde(default)]
+            timestamps: bool,
+            #[serde(default, deserialize_with = "deserialize_size")]
+            max_size: Option<u64>,
+            #[serde(default = "LogFile::default_max_files")]
+            max_files: usize,
+        }
+
+        let value = toml::Value::deserialize(deserializer)?;
+        match value {
+            toml::Value::String(path) => Ok(LogFile {
+                path,
+                strip_ansi: false,
+                timestamps: false,
+                max_size: None,
+                max_files: LogFile::default_max_files(),
+            }),
+            toml::Value::Table(table) => {
+                Options::deserialize(table).map_err(|err| de::Error::custom(err.message()))
+            }
+            value => Err(de::Error::invalid_type(
+                de::Unexpected::Other(value.type_str()),
+                &"string or table",
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Env {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = toml::Value::deserialize(deserializer)?;
+        match value {
+            toml::Value::String(s) => Ok(Env::File(PathBuf::from(s))),
+            toml::Value::Table(table) => table
+                .into_iter()
+                .map(|(key, value)| match value {
+                    toml::Value::String(s) => Ok((key, s)),
+                    toml::Value::Integer(i) => Ok((key, i.to_string())),
+                    toml::Value::Float(f) => Ok((key, f.to_string())),
+                    toml::Value::Boolean(b) => Ok((key, b.to_string())),
+                    toml::Value::Datetime(_) => Err(de::Error::invalid_type(
+                        de::Unexpected::Other("datetime"),
+                        &"string, number or boolean",
+                    )),
+                    toml::Value::Array(_) => Err(de::Error::invalid_type(
+                        de::Unexpected::Other("array"),
+                        &"string, number or boolean",
+                    )),
+                    toml::Value::Table(_) => Err(de::Error::invalid_type(
+                        de::Unexpected::Other("table"),
+                        &"string, number or boolean",
+                    )),
+                })
+                .collect::<Result<BTreeMap<_, _>, _>>()
+                .map(Env::Vars),
+            toml::Value::Integer(i) => Err(de::Error::invalid_type(
+                de::Unexpected::Signed(i),
+                &"string or table",
+            )),
+            toml::Value::Float(f) => Err(de::Error::invalid_type(
+                de::Unexpected::Float(f),
+                &"string or table",
+            )),
+            toml::Value::Boolean(b) => Err(de::Error::invalid_type(
+                de::Unexpected::Bool(b),
+                &"string or table",
+            )),
+            toml::Value::Datetime(_) => Err(de::Error::invalid_type(
+                de::Unexpected::Other("datetime"),
+                &"string or table",
+            )),
+            toml::Value::Array(_) => Err(de::Error::invalid_type(
+                de::Unexpected::Other("array"),
+                &"string or table",
+            )),
+        }
+    }
+}
+
+fn deserialize_one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged, expecting = "a value or an array of values")]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => Ok(vec![value]),
+        OneOrMany::Many(values) => Ok(values),
+    }
+}
+
+/// Accepts a number of bytes, or a string such as `512KB`, `10MB` or `1GB`.
+fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = toml::Value::deserialize(deserializer)?;
+    let size = match &value {
+        toml::Value::Integer(bytes) => u64::try_from(*bytes).ok(),
+        toml::Value::String(s) => {
+            let s = s.trim().to_uppercase();
+            let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let (number, unit) = s.split_at(split);
+            let unit = match unit.trim() {
+                "" | "B" => Some(1),
+                "KB" => Some(1 << 10),
+                "MB" => Some(1 << 20),
+                "GB" => Some(1 << 30),
+                _ => None,
+            };
+            number
+                .parse::<u64>()
+                .ok()
+                .zip(unit)
+                .and_then(|(number, unit)| number.checked_mul(unit))
+        }
+        _ => None,
+    };
+    match size {
+        Some(size) if size > 0 => Ok(Some(size)),
+        _ => Err(de::Error::custom(format!(
+            "invalid size {value}, expected a number of bytes or a size such as 10MB"
+        ))),
+    }
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(de::Error::custom)
+}
+
+fn deserialize_http_url<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let url = String::deserialize(deserializer)?;
+    if !url.starts_with("http://") {
+        return Err(de::Error::custom(format!(
+            "unsupported url {url}, only http:// urls can be probed"
+        )));
+    }
+    Ok(url)
+}
+
+fn deserialize_signal<'de, D>(deserializer: D) -> Result<Signal, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    parse_signal(&name).ok_or_else(|| de::Error::custom(format!("unknown signal {name}")))
+}
+
+/// Accepts signal names with or without the `SIG` prefix, such as `SIGINT` or `int`.
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    let name = name.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{name}")
+    };
+    name.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(jitter: f64) -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(5),
+            factor: 2.0,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_by_factor() {
+        let backoff = backoff(0.0);
+
+        assert_eq!(backoff.delay(0), Duration::from_millis(500));
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(3), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn backoff_is_clamped_to_max() {
+        let backoff = backoff(0.0);
+
+        assert_eq!(backoff.delay(4), Duration::from_secs(5));
+        assert_eq!(backoff.delay(10_000), Duration::from_secs(5));
+        assert_eq!(backoff.delay(usize::MAX), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_bounds() {
+        let backoff = backoff(0.1);
+
+        for _ in 0..1000 {
+            let delay = backoff.delay(1);
+            assert!(delay >= Duration::from_millis(900) && delay <= Duration::from_millis(1100));
+            assert!(backoff.delay(20) <= Duration::from_secs(5));
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{
+    config::Config,
+    layer::{self, Sources},
+};
+
+const TASKS_FILE: &str = "tasks.toml";
+const LOCAL_TASKS_FILE: &str = "tasks.local.toml";
+const CARGO_FILE: &str = "Cargo.toml";
+
+/// A raw config along with the file it was loaded from.
+///
+/// The config is kept as a table until [`ConfigFile::parse`], so that profiles can be applied to it.
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub table: toml::Table,
+    pub sources: Sources,
+    /// Every file merged into the config, in order.
+    pub files: Vec<SourceFile>,
+}
+
+/// A file the config was read from, kept so that problems can be reported against it.
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub contents: String,
+    /// Keys of the config within the file, such as `package.metadata.concurrently`.
+    pub prefix: Vec<&'static str>,
+}
+
+impl SourceFile {
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(SourceFile {
+            path: path.to_path_buf(),
+            contents,
+            prefix: Vec::new(),
+        })
+    }
+
+    fn parse(&self) -> anyhow::Result<toml::Table> {
+        toml::from_str(&self.contents)
+            .with_context(|| format!("failed to parse {}", self.path.display()))
+    }
+}
+
+impl ConfigFile {
+    /// Directory containing the config, which relative paths are resolved from.
+    pub fn root(&self) -> PathBuf {
+        self.path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
+    pub fn parse(self) -> anyhow::Result<Config> {
+        Config::deserialize(self.table)
+            .with_context(|| format!("invalid config in {}", self.path.display()))
+    }
+
+    /// Merges a file into the config, after the files it `extends` and `include`s.
+    fn merge_file(
+        &mut self,
+        file: SourceFile,
+        mut table: toml::Table,
+        stack: &mut Vec<PathBuf>,
+    ) -> anyhow::Result<()> {
+        let path = file.path.as_path();
+        if stack.iter().any(|included| included == path) {
+            stack.push(path.to_path_buf());
+            let chain: Vec<_> = stack
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            bail!("config includes itself: {}", chain.join(" -> "));
+        }
+        stack.push(path.to_path_buf());
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for extends in take_paths(&mut table, "extends", path)? {
+            let extended = SourceFile::read(&dir.join(extends))?;
+            let extended_table = extended.parse()?;
+            self.merge_file(extended, extended_table, stack)?;
+        }
+        for pattern in take_paths(&mut table, "include", path)? {
+            let pattern = dir.join(pattern);
+            let mut paths = glob::glob(&pattern.to_string_lossy())
+                .with_context(|| format!("invalid include pattern {}", pattern.display()))?
+                .collect::<Result<Vec<_>, _>>()?;
+            if paths.is_empty() && !pattern.to_string_lossy().contains(['*', '?', '[']) {
+                bail!("included config {} not found", pattern.display());
+            }
+            paths.sort();
+            for included in paths {
+                let included = SourceFile::read(&included)?;
+                let included_table = included.parse()?;
+                self.merge_file(included, included_table, stack)?;
+            }
+        }
+
+        let origin = path
+            .strip_prefix(self.root())
+            .unwrap_or(path)
+            .display()
+            .to_string();
+        layer::merge(&mut self.table, table, "", &origin, &mut self.sources);
+        stack.pop();
+        self.files.push(file);
+
+        Ok(())
+    }
+}
+
+/// Loads the config from `path` if given, otherwise searches the current directory and its parents.
+///
+/// In each directory a `tasks.toml` is preferred, followed by `[workspace.metadata.concurrently]`
+/// or `[package.metadata.concurrently]` in `Cargo.toml`. The search stops at the cargo workspace
+/// root or git repository root.
+///
+/// A `tasks.local.toml` next to the config is merged on top of it.
+pub fn load(path: Option<&Path>) -> anyhow::Result<ConfigFile> {
+    let (file, table) = find(path)?;
+    let mut config_file = ConfigFile {
+        path: file.path.clone(),
+        table: toml::Table::new(),
+        sources: Sources::default(),
+        files: Vec::new(),
+    };
+    let local_path = config_file.root().join(local_file_name(&file.path));
+    config_file.merge_file(file, table, &mut Vec::new())?;
+
+    if local_path.is_file() {
+        let local = SourceFile::read(&local_path)?;
+        let local_table = local.parse()?;
+        config_file.merge_file(local, local_table, &mut Vec::new())?;
+    }
+
+    Ok(config_file)
+}
+
+fn find(path: Option<&Path>) -> anyhow::Result<(SourceFile, toml::Table)> {
+    match path {
+        Some(path) => {
+            let path = fs::canonicalize(path)
+                .with_context(|| format!("config {} not found", path.display()))?;
+            let mut file = SourceFile::read(&path)?;
+            let table = if path.file_name().is_some_and(|name| name == CARGO_FILE) {
+                let (table, prefix) =
+                    cargo_metadata(&file.parse()?, &path)?.with_context(|| {
+                        format!("no concurrently metadata found in {}", path.display())
+                    })?;
+                file.prefix = prefix;
+                table
+            } else {
+                file.parse()?
+            };
+
+            Ok((file, table))
+        }
+        None => search(&env::current_dir().context("could not get current directory")?),
+    }
+}
+
+fn search(start: &Path) -> anyhow::Result<(SourceFile, toml::Table)> {
+    for dir in start.ancestors() {
+        let path = dir.join(TASKS_FILE);
+        if path.is_file() {
+            let file = SourceFile::read(&path)?;
+            let table = file.parse()?;
+            return Ok((file, table));
+        }
+
+        let path = dir.join(CARGO_FILE);
+        if path.is_file() {
+            let mut file = SourceFile::read(&path)?;
+            let manifest = file.parse()?;
+            if let Some((table, prefix)) = cargo_metadata(&manifest, &path)? {
+                file.prefix = prefix;
+                return Ok((file, table));
+            }
+            if manifest.contains_key("workspace") {
+                break;
+            }
+        }
+
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+
+    bail!("no {TASKS_FILE} or concurrently metadata in {CARGO_FILE} found")
+}
+
+/// `tasks.toml` and `Cargo.toml` use `tasks.local.toml`, and other files such as `dev.toml` use
+/// `dev.local.toml`.
+fn local_file_name(path: &Path) -> String {
+    match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(stem) if path.file_name().is_some_and(|name| name != CARGO_FILE) => {
+            format!("{stem}.local.toml")
+        }
+        _ => LOCAL_TASKS_FILE.to_string(),
+    }
+}
+
+/// Removes a key holding a path or array of paths from the table.
+fn take_paths(table: &mut toml::Table, key: &str, path: &Path) -> anyhow::Result<Vec<String>> {
+    match table.remove(key) {
+        None => Ok(Vec::new()),
+        Some(toml::Value::String(s)) => Ok(vec![s]),
+        Some(toml::Value::Array(values)) => values
+            .into_iter()
+            .map(|value| match value {
+                toml::Value::String(s) => Ok(s),
+                _ => bail!(
+                    "{key} in {} must be a path or array of paths",
+                    path.display()
+                ),
+            })
+            .collect(),
+        Some(_) => bail!(
+            "{key} in {} must be a path or array of paths",
+            path.display()
+        ),
+    }
+}
+
+/// Only the tables we need are read from the manifest, so that fields from newer versions of cargo
+/// don't stop us finding the config. The keys of the table within the manifest are returned with it.
+fn cargo_metadata(
+    manifest: &toml::Table,
+    path: &Path,
+) -> anyhow::Result<Option<(toml::Table, Vec<&'static str>)>> {
+    let metadata = |section: &'static str| {
+        let table = manifest
+            .get(section)?
+            .get("metadata")?
+            .get("concurrently")?;
+        Some((table, vec![section, "metadata", "concurrently"]))
+    };
+
+    match metadata("workspace").or_else(|| metadata("package")) {
+        Some((toml::Value::Table(table), prefix)) => Ok(Some((table.clone(), prefix))),
+        Some(_) => bail!(
+            "invalid concurrently metadata in {}, expected a table",
+            path.display()
+        ),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    /// Writes `files` to a new directory and loads the config from its `tasks.toml`, returning
+    /// each config value along with the file `--sources` reports it came from.
+    fn load_files(name: &str, files: &[(&str, &str)]) -> Vec<(String, String, String)> {
+        let dir = env::temp_dir().join(format!("concurrently-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let config_file = load(Some(&dir.join(TASKS_FILE))).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        layer::leaves(&config_file.table)
+            .into_iter()
+            .map(|(key, value)| {
+                let source = config_file.sources.get(&key).unwrap_or("unknown");
+                (key, value.to_string(), source.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_the_file_of_each_value() {
+        let files = [
+            (
+                "tasks.toml",
+                "extends = 'shared/base.toml'\ninclude = ['tasks/*.toml']\n[tasks.web]\ncommand = 'serve'",
+            ),
+            (
+                "shared/base.toml",
+                "[tasks.web]\ncommand = 'base'\ncwd = 'web'",
+            ),
+            ("tasks/db.toml", "[tasks.db]\ncommand = 'postgres'"),
+            ("tasks.local.toml", "[tasks.db]\nenv = { PORT = '5433' }"),
+        ];
+        let expected = [
+            ("tasks.db.command", "\"postgres\"", "tasks/db.toml"),
+            ("tasks.db.env.PORT", "\"5433\"", "tasks.local.toml"),
+            ("tasks.web.command", "\"serve\"", "tasks.toml"),
+            ("tasks.web.cwd", "\"web\"", "shared/base.toml"),
+        ];
+
+        let values = load_files("sources", &files);
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(key, value, source)| (key.to_string(), value.to_string(), source.to_string()))
+            .collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn local_file_takes_precedence() {
+        let files = [
+            (
+                "tasks.toml",
+                "include = 'more.toml'\n[tasks.web]\ncommand = 'serve'\nargs = ['--port', '80']",
+            ),
+            ("more.toml", "[tasks.web]\nrestart = true"),
+            (
+                "tasks.local.toml",
+                "[tasks.web]\nargs = ['--port', '8080']\nrestart = false",
+            ),
+        ];
+        let expected = [
+            (
+                "tasks.web.args",
+                "[\"--port\", \"8080\"]",
+                "tasks.local.toml",
+            ),
+            ("tasks.web.command", "\"serve\"", "tasks.toml"),
+            ("tasks.web.restart", "false", "tasks.local.toml"),
+        ];
+
+        let values = load_files("local", &files);
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(key, value, source)| (key.to_string(), value.to_string(), source.to_string()))
+            .collect();
+        assert_eq!(values, expected);
+    }
+}
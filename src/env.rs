@@ -0,0 +1,153 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    config::{Env, EnvOptions},
+    interpolate,
+    log::warn,
+};
+
+/// Environment variables resolved for each task, without touching concurrently's own environment.
+///
+/// Variables are applied in order of precedence, with later ones overriding earlier ones:
+///
+/// 1. Variables inherited from concurrently, unless `clear_env` or `env_allow` is set.
+/// 2. Global env files.
+/// 3. Global `env` table.
+/// 4. Task env files.
+/// 5. Task `env` table.
+pub struct Environment {
+    root: PathBuf,
+    clear_env: Option<bool>,
+    env_allow: Option<Vec<String>>,
+    files: BTreeMap<String, String>,
+    table: BTreeMap<String, String>,
+}
+
+impl Environment {
+    /// Env files are resolved relative to `root`.
+    pub fn load(opts: &EnvOptions, root: &Path) -> anyhow::Result<Self> {
+        Ok(Environment {
+            root: root.to_path_buf(),
+            clear_env: opts.clear_env,
+            env_allow: opts.env_allow.clone(),
+            files: read_env_files(opts, root)?,
+            table: env_table(opts),
+        })
+    }
+
+    /// Values from `env` tables are passed through `expand`, along with the variables from
+    /// lower precedence sources which they can refer to.
+    pub fn for_task(
+        &self,
+        opts: &EnvOptions,
+        mut expand: impl FnMut(&str, &BTreeMap<String, String>) -> anyhow::Result<String>,
+    ) -> anyhow::Result<BTreeMap<String, String>> {
+        let clear_env = opts.clear_env.or(self.clear_env).unwrap_or(false);
+        let env_allow = opts.env_allow.as_ref().or(self.env_allow.as_ref());
+
+        let mut vars: BTreeMap<_, _> = match env_allow {
+            Some(allow) => env::vars().filter(|(key, _)| allow.contains(key)).collect(),
+            None if clear_env => BTreeMap::new(),
+            None => env::vars().collect(),
+        };
+        vars.extend(self.files.clone());
+        extend_expanded(&mut vars, &self.table, &mut expand)?;
+        vars.extend(read_env_files(opts, &self.root)?);
+        extend_expanded(&mut vars, &env_table(opts), &mut expand)?;
+
+        Ok(vars)
+    }
+}
+
+/// Expands the values of an env table, which can refer to each other as well as to `vars`.
+/// A variable referring to itself gets the value it had before the table was applied.
+fn extend_expanded(
+    vars: &mut BTreeMap<String, String>,
+    table: &BTreeMap<String, String>,
+    expand: &mut impl FnMut(&str, &BTreeMap<String, String>) -> anyhow::Result<String>,
+) -> anyhow::Result<()> {
+    let mut expanded = BTreeMap::new();
+    let mut visiting = Vec::new();
+    for key in table.keys() {
+        expand_var(key, vars, table, &mut expanded, &mut visiting, expand)?;
+    }
+    vars.extend(expanded);
+    Ok(())
+}
+
+fn expand_var(
+    key: &str,
+    vars: &BTreeMap<String, String>,
+    table: &BTreeMap<String, String>,
+    expanded: &mut BTreeMap<String, String>,
+    visiting: &mut Vec<String>,
+    expand: &mut impl FnMut(&str, &BTreeMap<String, String>) -> anyhow::Result<String>,
+) -> anyhow::Result<()> {
+    if expanded.contains_key(key) {
+        return Ok(());
+    }
+    if visiting.iter().any(|visiting| visiting == key) {
+        visiting.push(key.to_string());
+        bail!(
+            "cyclic reference between variables {}",
+            visiting.join(" -> ")
+        );
+    }
+
+    let value = &table[key];
+    visiting.push(key.to_string());
+    for name in interpolate::variables(value) {
+        if name != key && table.contains_key(name) {
+            expand_var(name, vars, table, expanded, visiting, expand)?;
+        }
+    }
+    visiting.pop();
+
+    let mut visible = vars.clone();
+    visible.extend(expanded.clone());
+    let value = expand(value, &visible)?;
+    expanded.insert(key.to_string(), value);
+
+    Ok(())
+}
+
+fn read_env_files(opts: &EnvOptions, root: &Path) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+    if let Some(Env::File(path)) = &opts.env {
+        vars.extend(read_env_file(&root.join(path))?);
+    }
+    for path in &opts.env_file {
+        vars.extend(read_env_file(&root.join(path))?);
+    }
+
+    Ok(vars)
+}
+
+fn env_table(opts: &EnvOptions) -> BTreeMap<String, String> {
+    match &opts.env {
+        Some(Env::Vars(table)) => table.clone(),
+        _ => BTreeMap::new(),
+    }
+}
+
+fn read_env_file(path: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    // The iterator is deprecated in favour of loading into the process environment,
+    // which is exactly what we want to avoid.
+    #[allow(deprecated)]
+    let iter = match dotenv::from_path_iter(path) {
+        Ok(iter) => iter,
+        Err(_) => {
+            warn(format!("failed to load env file {}", path.display()));
+            return Ok(Vec::new());
+        }
+    };
+
+    iter.collect::<Result<_, _>>()
+        .with_context(|| format!("failed to parse env file {}", path.display()))
+}
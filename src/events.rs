@@ -0,0 +1,154 @@
+use std::{
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use humantime_serde::re::humantime;
+use nix::sys::signal::Signal;
+use serde::Serialize;
+
+use crate::summary::{Outcome, TaskSummary};
+
+/// Set by `--output json`, which prints events instead of text.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Something that happened during the run, printed as a line of JSON tagged by `event`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A task started building or running its `prepare` command.
+    PrepareStart { task: &'a str },
+    /// A task finished preparing, taking `duration` seconds.
+    PrepareEnd {
+        task: &'a str,
+        success: bool,
+        #[serde(flatten)]
+        exit: Option<Exit>,
+        duration: f64,
+    },
+    /// A task's process started.
+    Spawn { task: &'a str, pid: Option<u32> },
+    /// A line of a task's output, without escape sequences.
+    Output {
+        task: &'a str,
+        stream: Stream,
+        line: String,
+        /// When the line was printed, in RFC 3339 format.
+        time: String,
+    },
+    /// A task became ready.
+    Ready { task: &'a str },
+    /// A task's process exited.
+    Exit {
+        task: &'a str,
+        #[serde(flatten)]
+        exit: Exit,
+    },
+    /// A task will be restarted after `delay` seconds.
+    Retry {
+        task: &'a str,
+        reason: &'a str,
+        restart: usize,
+        delay: f64,
+    },
+    /// A message from concurrently itself, including problems found in the config.
+    Log { level: Level, message: String },
+    /// A config value printed by `--sources`, in TOML syntax, and the file which set it.
+    Source {
+        key: &'a str,
+        value: String,
+        source: &'a str,
+    },
+    /// The run is over, and concurrently exits with `code`.
+    Shutdown { code: i32, tasks: Vec<TaskResult> },
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+/// How a process exited, with either its exit code or the signal which killed it.
+#[derive(Serialize)]
+pub struct Exit {
+    code: Option<i32>,
+    signal: Option<&'static str>,
+}
+
+impl From<ExitStatus> for Exit {
+    fn from(status: ExitStatus) -> Self {
+        Exit {
+            code: status.code(),
+            signal: status
+                .signal()
+                .and_then(|signal| Signal::try_from(signal).ok())
+                .map(Signal::as_str),
+        }
+    }
+}
+
+/// A task's row of the summary, sent with [`Event::Shutdown`].
+#[derive(Serialize)]
+pub struct TaskResult {
+    name: String,
+    status: &'static str,
+    #[serde(flatten)]
+    exit: Option<Exit>,
+    restarts: usize,
+    /// Time spent running in seconds, across all runs.
+    uptime: f64,
+    /// Seconds taken to prepare the task, if it had anything to prepare.
+    prepare: Option<f64>,
+}
+
+impl From<&TaskSummary<'_>> for TaskResult {
+    fn from(summary: &TaskSummary) -> Self {
+        TaskResult {
+            name: summary.name.to_string(),
+            status: match summary.outcome {
+                Outcome::Succeeded => "succeeded",
+                Outcome::Failed => "failed",
+                Outcome::Stopped => "stopped",
+                Outcome::Skipped => "skipped",
+            },
+            exit: summary.exit_status.map(Exit::from),
+            restarts: summary.restarts,
+            uptime: summary.uptime.as_secs_f64(),
+            prepare: summary.prepare.as_ref().map(Duration::as_secs_f64),
+        }
+    }
+}
+
+/// Prints events from now on, and no text.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Prints an event, if events are enabled.
+pub fn emit(event: Event) {
+    if enabled() {
+        let json = serde_json::to_string(&event).expect("events serialize to JSON");
+        println!("{json}");
+    }
+}
+
+/// Formats a time as UTC in RFC 3339 format, with milliseconds.
+pub fn time(time: SystemTime) -> String {
+    humantime::format_rfc3339_millis(time).to_string()
+}
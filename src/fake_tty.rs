@@ -1,22 +1,40 @@
 #![allow(
-    clippy::empty_enum,
     clippy::let_underscore_untyped,
     clippy::needless_pass_by_value,
     clippy::uninlined_format_args
 )]
 
 use nix::fcntl::{self, FcntlArg, FdFlag};
-use nix::pty::{self, ForkptyResult, Winsize};
+use nix::pty::{self, OpenptyResult, Winsize};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
 use nix::sys::wait::{self, WaitStatus};
 use nix::unistd::{self, ForkResult, Pid};
-use nix::Result;
+use nix::{libc, Result};
+use std::convert::Infallible;
 use std::ffi::CString;
 use std::io::{self, Write};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::RawFd;
 use std::process;
+use std::thread;
 
-enum Exec {}
+/// Passed before the command to connect its stdin to the pty, with the fake tty's own stdin
+/// copied into it, so that keyboard input goes through the pty's line discipline.
+pub const PTY_STDIN: &str = "--pty-stdin";
+
+/// Signals meant for the task, which are delivered to its whole process group. The fake tty
+/// outlives them so that it can pass on the rest of the task's output and its exit status, and
+/// isn't suspended by Ctrl-Z.
+const TASK_SIGNALS: [Signal; 8] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGALRM,
+    Signal::SIGTSTP,
+];
 
 pub fn run() {
     match try_main() {
@@ -28,40 +46,59 @@ pub fn run() {
     }
 }
 
-fn try_main() -> Result<Exec> {
-    let args = args();
-    let stdin = dup(0)?;
-    let stderr = dup(2)?;
-    let pty1 = unsafe { forkpty() }?;
-    if let ForkResult::Parent { child } = pty1.fork_result {
-        copyfd(pty1.master, 1);
-        copyexit(child);
+/// Runs the command with a pty for each of stdout and stderr, where stdin shares the stdout pty
+/// with [`PTY_STDIN`].
+///
+/// The fake tty is started in a session of its own, and makes the stdout pty the session's
+/// controlling terminal, so that Ctrl-C and Ctrl-Z typed into it become signals and `/dev/tty`
+/// works. The command stays in the fake tty's process group, so that signalling or killing the
+/// group reaches everything the task started.
+fn try_main() -> Result<Infallible> {
+    let (pty_stdin, args) = args();
+    let stdout = openpty()?;
+    let stderr = openpty()?;
+    for fd in [stdout.master, stdout.slave, stderr.master, stderr.slave] {
+        fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
     }
-    let stdout = dup(1)?;
-    let pty2 = unsafe { forkpty() }?;
-    if let ForkResult::Parent { child } = pty2.fork_result {
-        copyfd(pty2.master, stderr);
-        copyexit(child);
+    if unsafe { libc::ioctl(stdout.slave, libc::TIOCSCTTY as _, 0) } == -1 {
+        return Err(nix::Error::last());
     }
-    unistd::dup2(stdin, 0)?;
-    unistd::dup2(stdout, 1)?;
-    exec(args)
-}
+    // Handlers are reset by exec, so the command still gets the default behaviour.
+    outlive_task_signals()?;
 
-fn args() -> Vec<CString> {
-    std::env::args_os()
-        .skip(2)
-        .map(|os_string| CString::new(os_string.as_bytes()).unwrap())
-        .collect()
+    match unsafe { unistd::fork() }? {
+        ForkResult::Parent { child } => {
+            unistd::close(stdout.slave)?;
+            unistd::close(stderr.slave)?;
+            if pty_stdin {
+                thread::spawn(move || copyfd(0, stdout.master));
+            }
+            let stderr_copy = thread::spawn(move || copyfd(stderr.master, 2));
+            copyfd(stdout.master, 1);
+            let _ = stderr_copy.join();
+            copyexit(child);
+        }
+        ForkResult::Child => {
+            if pty_stdin {
+                unistd::dup2(stdout.slave, 0)?;
+            }
+            unistd::dup2(stdout.slave, 1)?;
+            unistd::dup2(stderr.slave, 2)?;
+            exec(args)
+        }
+    }
 }
 
-fn dup(fd: RawFd) -> Result<RawFd> {
-    let new = unistd::dup(fd)?;
-    fcntl::fcntl(new, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
-    Ok(new)
+fn args() -> (bool, Vec<CString>) {
+    let mut args = std::env::args_os().skip(2).peekable();
+    let pty_stdin = args.next_if(|arg| arg == PTY_STDIN).is_some();
+    let args = args
+        .map(|os_string| CString::new(os_string.as_bytes()).unwrap())
+        .collect();
+    (pty_stdin, args)
 }
 
-unsafe fn forkpty() -> Result<ForkptyResult> {
+fn openpty() -> Result<OpenptyResult> {
     let winsize = Winsize {
         ws_row: 24,
         ws_col: 80,
@@ -69,10 +106,24 @@ unsafe fn forkpty() -> Result<ForkptyResult> {
         ws_ypixel: 0,
     };
     let termios = None;
-    pty::forkpty(&winsize, termios)
+    pty::openpty(&winsize, termios)
+}
+
+fn outlive_task_signals() -> Result<()> {
+    extern "C" fn ignore(_: i32) {}
+
+    let action = SigAction::new(
+        SigHandler::Handler(ignore),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for sig in TASK_SIGNALS {
+        unsafe { signal::sigaction(sig, &action) }?;
+    }
+    Ok(())
 }
 
-fn exec(args: Vec<CString>) -> Result<Exec> {
+fn exec(args: Vec<CString>) -> Result<Infallible> {
     let args: Vec<_> = args.iter().map(CString::as_c_str).collect();
     unistd::execvp(args[0], &args)?;
     unreachable!();
@@ -103,6 +154,14 @@ fn copyexit(child: Pid) -> ! {
     let flag = None;
     process::exit(match wait::waitpid(child, flag) {
         Ok(WaitStatus::Exited(_pid, code)) => code,
+        Ok(WaitStatus::Signaled(_pid, sig, _core_dumped)) => {
+            // Die from the same signal, so that the task is seen as killed rather than exited.
+            unsafe {
+                let _ = signal::signal(sig, SigHandler::SigDfl);
+            }
+            let _ = signal::raise(sig);
+            128 + sig as i32
+        }
         _ => 0,
     });
 }
@@ -0,0 +1,215 @@
+use std::{collections::VecDeque, mem};
+
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt},
+    time::{self, Duration},
+};
+
+/// How long an unfinished line waits for more output before it's shown anyway, so that prompts
+/// without a newline appear.
+const IDLE: Duration = Duration::from_millis(100);
+
+/// A line of a task's output.
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    /// A line ended by a newline, which may be blank.
+    Done(String),
+    /// The line so far, either before it was overwritten after a `\r` or because the task went
+    /// quiet before ending it. It's replaced by whatever comes next for the same line.
+    Partial(String),
+}
+
+/// Splits a task's output into lines as bytes come in, rather than waiting for each newline.
+///
+/// `\r\n` ends a line like `\n` does, while any other `\r` starts the line over, as progress bars
+/// do to redraw themselves.
+pub struct Forwarder<R> {
+    reader: R,
+    buf: Box<[u8]>,
+    /// Lines ready to be returned.
+    ready: VecDeque<Line>,
+    /// The unfinished line.
+    line: Vec<u8>,
+    /// Whether the last byte was a `\r`, which starts the line over unless a `\n` follows.
+    carriage: bool,
+    /// Whether the unfinished line has already been returned as it is.
+    shown: bool,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> Forwarder<R> {
+    pub fn new(reader: R) -> Self {
+        Forwarder {
+            reader,
+            buf: vec![0; 8192].into_boxed_slice(),
+            ready: VecDeque::new(),
+            line: Vec::new(),
+            carriage: false,
+            shown: false,
+            eof: false,
+        }
+    }
+
+    /// Returns the next line, or `None` once the output has ended.
+    pub async fn next(&mut self) -> io::Result<Option<Line>> {
+        loop {
+            if let Some(line) = self.ready.pop_front() {
+                return Ok(Some(line));
+            }
+            if self.eof {
+                return Ok(None);
+            }
+
+            let read = self.reader.read(&mut self.buf);
+            let read = if self.line.is_empty() || self.shown {
+                read.await?
+            } else {
+                match time::timeout(IDLE, read).await {
+                    Ok(read) => read?,
+                    Err(_) => {
+                        self.shown = true;
+                        return Ok(Some(Line::Partial(text(&self.line))));
+                    }
+                }
+            };
+
+            if read == 0 {
+                self.eof = true;
+                if !self.line.is_empty() {
+                    self.ready.push_back(Line::Done(text(&self.line)));
+                }
+            } else {
+                for i in 0..read {
+                    self.push(self.buf[i]);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if mem::take(&mut self.carriage) && byte != b'\n' {
+            self.overwrite();
+        }
+        match byte {
+            b'\n' => {
+                self.ready.push_back(Line::Done(text(&self.line)));
+                self.line.clear();
+                self.shown = false;
+            }
+            b'\r' => self.carriage = true,
+            byte => {
+                self.line.push(byte);
+                self.shown = false;
+            }
+        }
+    }
+
+    /// Starts the line over, showing what it had so far unless it's been shown already.
+    fn overwrite(&mut self) {
+        if !self.shown && !self.line.is_empty() {
+            // Only the last redraw read at once is worth showing.
+            if let Some(Line::Partial(_)) = self.ready.back() {
+                self.ready.pop_back();
+            }
+            self.ready.push_back(Line::Partial(text(&self.line)));
+        }
+        self.line.clear();
+        self.shown = false;
+    }
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncWriteExt, DuplexStream},
+        time::Instant,
+    };
+
+    use super::*;
+
+    fn done(line: &str) -> Line {
+        Line::Done(line.to_string())
+    }
+
+    fn partial(line: &str) -> Line {
+        Line::Partial(line.to_string())
+    }
+
+    /// Reads every line of output which is written all at once.
+    async fn lines(output: &[u8]) -> Vec<Line> {
+        let mut forwarder = Forwarder::new(output);
+        let mut lines = Vec::new();
+        while let Some(line) = forwarder.next().await.unwrap() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn pipe() -> (DuplexStream, Forwarder<DuplexStream>) {
+        let (writer, reader) = io::duplex(1024);
+        (writer, Forwarder::new(reader))
+    }
+
+    #[tokio::test]
+    async fn splits_lines() {
+        assert_eq!(lines(b"one\ntwo\n").await, [done("one"), done("two")]);
+    }
+
+    #[tokio::test]
+    async fn crlf_ends_a_line() {
+        assert_eq!(lines(b"one\r\ntwo\r\n").await, [done("one"), done("two")]);
+    }
+
+    #[tokio::test]
+    async fn keeps_blank_lines() {
+        assert_eq!(
+            lines(b"one\n\n\r\ntwo\n").await,
+            [done("one"), done(""), done(""), done("two")]
+        );
+    }
+
+    #[tokio::test]
+    async fn ends_unfinished_line_at_eof() {
+        assert_eq!(lines(b"one\ntwo").await, [done("one"), done("two")]);
+        assert_eq!(lines(b"one\r").await, [done("one")]);
+    }
+
+    #[tokio::test]
+    async fn shows_last_redraw_read_at_once() {
+        assert_eq!(
+            lines(b"10%\r50%\r100%\n").await,
+            [partial("50%"), done("100%")]
+        );
+    }
+
+    #[tokio::test]
+    async fn shows_unfinished_line_once_idle() {
+        let (mut writer, mut forwarder) = pipe();
+        writer.write_all(b"name? ").await.unwrap();
+        let start = Instant::now();
+        assert_eq!(forwarder.next().await.unwrap(), Some(partial("name? ")));
+        assert!(start.elapsed() >= IDLE);
+
+        writer.write_all(b"me\n").await.unwrap();
+        assert_eq!(forwarder.next().await.unwrap(), Some(done("name? me")));
+        drop(writer);
+        assert_eq!(forwarder.next().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn replaces_progress_bar_as_it_redraws() {
+        let (mut writer, mut forwarder) = pipe();
+        writer.write_all(b"[#   ]\r").await.unwrap();
+        assert_eq!(forwarder.next().await.unwrap(), Some(partial("[#   ]")));
+        writer.write_all(b"[##  ]\r").await.unwrap();
+        assert_eq!(forwarder.next().await.unwrap(), Some(partial("[##  ]")));
+        writer.write_all(b"[####]\r\n").await.unwrap();
+        assert_eq!(forwarder.next().await.unwrap(), Some(done("[####]")));
+        drop(writer);
+        assert_eq!(forwarder.next().await.unwrap(), None);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::bail;
+
+use crate::config::TaskOptions;
+
+/// Dependency graph between tasks, built from each task's `depends_on`.
+#[derive(Debug)]
+pub struct TaskGraph {
+    dependencies: BTreeMap<String, Vec<String>>,
+    order: Vec<String>,
+}
+
+impl TaskGraph {
+    pub fn new(tasks: &HashMap<String, TaskOptions>) -> anyhow::Result<Self> {
+        let mut dependencies = BTreeMap::new();
+        for (name, opts) in tasks {
+            for dependency in &opts.depends_on {
+                if dependency == name {
+                    bail!("task {name} depends on itself");
+                }
+                if !tasks.contains_key(dependency) {
+                    bail!("task {name} depends on unknown task {dependency}");
+                }
+            }
+            dependencies.insert(name.clone(), opts.depends_on.clone());
+        }
+
+        let mut graph = TaskGraph {
+            dependencies,
+            order: Vec::with_capacity(tasks.len()),
+        };
+        graph.order = graph.sort()?;
+
+        Ok(graph)
+    }
+
+    /// All task names in startup order, with dependencies always coming before their dependents.
+    pub fn order(&self) -> &[String] {
+        &self.order
+    }
+
+    pub fn dependencies(&self, name: &str) -> &[String] {
+        self.dependencies
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns the selected tasks along with everything they transitively depend on.
+    pub fn with_dependencies<'a>(
+        &self,
+        selected: impl IntoIterator<Item = &'a str>,
+    ) -> BTreeSet<String> {
+        let mut included = BTreeSet::new();
+        let mut stack: Vec<_> = selected.into_iter().map(ToString::to_string).collect();
+        while let Some(name) = stack.pop() {
+            if included.insert(name.clone()) {
+                stack.extend(self.dependencies(&name).iter().cloned());
+            }
+        }
+        included
+    }
+
+    fn sort(&self) -> anyhow::Result<Vec<String>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Visiting,
+            Visited,
+        }
+
+        fn visit<'a>(
+            graph: &'a TaskGraph,
+            name: &'a str,
+            marks: &mut HashMap<&'a str, Mark>,
+            path: &mut Vec<&'a str>,
+            order: &mut Vec<String>,
+        ) -> anyhow::Result<()> {
+            match marks.get(name) {
+                Some(Mark::Visited) => return Ok(()),
+                Some(Mark::Visiting) => {
+                    let start = path.iter().position(|n| *n == name).unwrap_or(0);
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(name);
+                    bail!("dependency cycle detected: {}", cycle.join(" -> "));
+                }
+                None => {}
+            }
+
+            marks.insert(name, Mark::Visiting);
+            path.push(name);
+            let mut dependencies: Vec<_> = graph.dependencies(name).iter().collect();
+            dependencies.sort();
+            for dependency in dependencies {
+                visit(graph, dependency, marks, path, order)?;
+            }
+            path.pop();
+            marks.insert(name, Mark::Visited);
+            order.push(name.to_string());
+
+            Ok(())
+        }
+
+        let mut marks = HashMap::new();
+        let mut path = Vec::new();
+        let mut order = Vec::with_capacity(self.dependencies.len());
+        for name in self.dependencies.keys() {
+            visit(self, name, &mut marks, &mut path, &mut order)?;
+        }
+
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the graph for the tasks in a `[tasks]` table.
+    fn graph(tasks: &str) -> anyhow::Result<TaskGraph> {
+        let tasks: HashMap<String, TaskOptions> = toml::from_str(tasks).unwrap();
+        TaskGraph::new(&tasks)
+    }
+
+    #[test]
+    fn orders_dependencies_first() {
+        let graph = graph(
+            r#"
+            web = { command = "serve", depends_on = ["api", "assets"] }
+            api = { command = "api", depends_on = ["db"] }
+            assets = { command = "build" }
+            db = { command = "postgres" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(graph.order(), ["db", "api", "assets", "web"]);
+        assert_eq!(graph.dependencies("web"), ["api", "assets"]);
+        assert!(graph.dependencies("db").is_empty());
+    }
+
+    #[test]
+    fn includes_transitive_dependencies() {
+        let graph = graph(
+            r#"
+            web = { command = "serve", depends_on = ["api"] }
+            api = { command = "api", depends_on = ["db"] }
+            db = { command = "postgres" }
+            worker = { command = "work", depends_on = ["db"] }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            graph.with_dependencies(["web"]),
+            BTreeSet::from(["web", "api", "db"].map(String::from))
+        );
+        assert_eq!(
+            graph.with_dependencies(["worker", "api"]),
+            BTreeSet::from(["worker", "api", "db"].map(String::from))
+        );
+    }
+
+    #[test]
+    fn reports_cycle_with_its_path() {
+        let err = graph(
+            r#"
+            a = { command = "a", depends_on = ["b"] }
+            b = { command = "b", depends_on = ["c"] }
+            c = { command = "c", depends_on = ["a"] }
+            "#,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "dependency cycle detected: a -> b -> c -> a"
+        );
+    }
+
+    #[test]
+    fn rejects_self_dependency() {
+        let err = graph(r#"a = { command = "a", depends_on = ["a"] }"#).unwrap_err();
+
+        assert_eq!(err.to_string(), "task a depends on itself");
+    }
+
+    #[test]
+    fn rejects_unknown_dependency() {
+        let err = graph(r#"a = { command = "a", depends_on = ["b"] }"#).unwrap_err();
+
+        assert_eq!(err.to_string(), "task a depends on unknown task b");
+    }
+}
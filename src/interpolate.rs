@@ -0,0 +1,406 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    config::{Command, ShellTaskOptions, TaskOptions, TaskTypeOptions},
+    env::Environment,
+};
+
+/// Expands `${VAR}`, `${VAR:-default}` and `${task.<name>.<field>}` placeholders in task configs.
+///
+/// Variables are looked up in the task's own environment, and `$${` is left as a literal `${`.
+/// Defaults may themselves contain placeholders, which are only expanded when the default is used.
+/// Other `${...}` expansions, such as `${f%.*}` or `${#x}`, are left for the shell.
+pub struct Resolver<'a> {
+    tasks: &'a HashMap<String, TaskOptions>,
+    environment: &'a Environment,
+    root: &'a Path,
+    envs: HashMap<String, BTreeMap<String, String>>,
+    resolving: Vec<String>,
+}
+
+enum Placeholder<'a> {
+    Var {
+        name: &'a str,
+        default: Option<&'a str>,
+    },
+    Task {
+        name: &'a str,
+        field: &'a str,
+    },
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(
+        tasks: &'a HashMap<String, TaskOptions>,
+        environment: &'a Environment,
+        root: &'a Path,
+    ) -> Self {
+        Resolver {
+            tasks,
+            environment,
+            root,
+            envs: HashMap::new(),
+            resolving: Vec::new(),
+        }
+    }
+
+    /// Returns the task's environment variables, with placeholders in `env` tables expanded.
+    pub fn env(&mut self, task: &str) -> anyhow::Result<BTreeMap<String, String>> {
+        if let Some(env) = self.envs.get(task) {
+            return Ok(env.clone());
+        }
+
+        let opts = self.options(task)?;
+        let environment = self.environment;
+        self.enter(task, "env")?;
+        let env = environment.for_task(&opts.env, |value, vars| self.expand(task, value, vars));
+        self.resolving.pop();
+
+        let env = env?;
+        self.envs.insert(task.to_string(), env.clone());
+        Ok(env)
+    }
+
+    /// Expands placeholders in the task's command, prepare and cwd.
+    pub fn expand_options(
+        &mut self,
+        task: &str,
+        mut opts: TaskOptions,
+    ) -> anyhow::Result<TaskOptions> {
+        if let TaskTypeOptions::Shell(ShellTaskOptions { command }) = &opts.task_options {
+            opts.task_options = TaskTypeOptions::Shell(ShellTaskOptions {
+                command: self.field_command(task, "command", command)?,
+            });
+        }
+        if let Some(prepare) = &opts.prepare {
+            opts.prepare = Some(self.field_command(task, "prepare", prepare)?);
+        }
+        if let Some(cwd) = &opts.cwd {
+            opts.cwd = Some(self.field_cwd(task, cwd)?);
+        }
+
+        Ok(opts)
+    }
+
+    fn options(&self, task: &str) -> anyhow::Result<&'a TaskOptions> {
+        let tasks = self.tasks;
+        tasks
+            .get(task)
+            .with_context(|| format!("unknown task {task} referenced in ${{task.{task}...}}"))
+    }
+
+    fn field(&mut self, task: &str, field: &str) -> anyhow::Result<String> {
+        let opts = self.options(task)?;
+        if let Some(key) = field.strip_prefix("env.") {
+            return self
+                .env(task)?
+                .remove(key)
+                .with_context(|| format!("undefined variable {key} in ${{task.{task}.{field}}}"));
+        }
+
+        match field {
+            "name" => Ok(task.to_string()),
+            "cwd" => match &opts.cwd {
+                Some(cwd) => {
+                    let cwd = self.field_cwd(task, cwd)?;
+                    Ok(self.root.join(cwd).to_string_lossy().into_owned())
+                }
+                None => Ok(self.root.to_string_lossy().into_owned()),
+            },
+            "command" => match &opts.task_options {
+                TaskTypeOptions::Shell(ShellTaskOptions { command }) => {
+                    Ok(self.field_command(task, field, command)?.to_string())
+                }
+                TaskTypeOptions::Cargo(_) => bail!("cargo task {task} has no command"),
+            },
+            "prepare" => match &opts.prepare {
+                Some(prepare) => Ok(self.field_command(task, field, prepare)?.to_string()),
+                None => bail!("task {task} has no prepare command"),
+            },
+            _ => bail!(
+                "unknown field {field} in ${{task.{task}.{field}}}, \
+                 expected name, cwd, command, prepare or env.<VAR>"
+            ),
+        }
+    }
+
+    fn field_command(
+        &mut self,
+        task: &str,
+        field: &str,
+        command: &Command,
+    ) -> anyhow::Result<Command> {
+        let vars = self.env(task)?;
+        self.enter(task, field)?;
+        let command = match command {
+            Command::String(s) => self.expand(task, s, &vars).map(Command::String),
+            Command::Args(args) => args
+                .iter()
+                .map(|arg| self.expand(task, arg, &vars))
+                .collect::<anyhow::Result<_>>()
+                .map(Command::Args),
+        };
+        self.resolving.pop();
+        command
+    }
+
+    fn field_cwd(&mut self, task: &str, cwd: &Path) -> anyhow::Result<PathBuf> {
+        let vars = self.env(task)?;
+        self.enter(task, "cwd")?;
+        let cwd = self.expand(task, &cwd.to_string_lossy(), &vars);
+        self.resolving.pop();
+        cwd.map(PathBuf::from)
+    }
+
+    fn enter(&mut self, task: &str, field: &str) -> anyhow::Result<()> {
+        let key = format!("task.{task}.{field}");
+        if self.resolving.contains(&key) {
+            self.resolving.push(key);
+            let cycle = self.resolving.join(" -> ");
+            self.resolving.clear();
+            bail!("cyclic reference {cycle}");
+        }
+        self.resolving.push(key);
+        Ok(())
+    }
+
+    fn expand(
+        &mut self,
+        task: &str,
+        input: &str,
+        vars: &BTreeMap<String, String>,
+    ) -> anyhow::Result<String> {
+        let mut output = String::with_capacity(input.len());
+        let mut rest = input;
+        while let Some(i) = rest.find('$') {
+            output.push_str(&rest[..i]);
+            rest = &rest[i..];
+            if let Some(after) = rest.strip_prefix("$${") {
+                output.push_str("${");
+                rest = after;
+                continue;
+            }
+            let Some(after) = rest.strip_prefix("${") else {
+                output.push('$');
+                rest = &rest[1..];
+                continue;
+            };
+
+            let end = closing_brace(after)
+                .with_context(|| format!("unclosed placeholder in task {task}: {input}"))?;
+            let value = match parse(&after[..end])
+                .with_context(|| format!("invalid placeholder in task {task}: {input}"))?
+            {
+                None => format!("${{{}}}", &after[..end]),
+                Some(Placeholder::Var { name, default }) => match (vars.get(name), default) {
+                    (Some(value), Some(default)) if value.is_empty() => {
+                        self.expand(task, default, vars)?
+                    }
+                    (Some(value), _) => value.clone(),
+                    (None, Some(default)) => self.expand(task, default, vars)?,
+                    (None, None) => bail!("undefined variable {name} in task {task}: {input}"),
+                },
+                Some(Placeholder::Task { name, field }) => self.field(name, field)?,
+            };
+            output.push_str(&value);
+            rest = &after[end + 1..];
+        }
+        output.push_str(rest);
+
+        Ok(output)
+    }
+}
+
+/// Names of the variables referred to by `${VAR}` placeholders in `input`, including those in
+/// defaults.
+pub fn variables(input: &str) -> Vec<&str> {
+    input
+        .match_indices("${")
+        .filter(|(i, _)| !input[..*i].ends_with('$'))
+        .filter_map(|(i, _)| {
+            let placeholder = &input[i + 2..];
+            match parse(&placeholder[..closing_brace(placeholder)?]) {
+                Ok(Some(Placeholder::Var { name, .. })) => Some(name),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Finds the `}` closing a placeholder, skipping over braces nested within it, as in
+/// `${A:-${B}}`.
+fn closing_brace(placeholder: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in placeholder.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses what's between `${` and `}`, returning `None` for any other shell expansion.
+fn parse(placeholder: &str) -> anyhow::Result<Option<Placeholder<'_>>> {
+    if let Some(rest) = placeholder.strip_prefix("task.") {
+        let (name, field) = rest
+            .split_once('.')
+            .context("expected ${task.<name>.<field>}")?;
+        return Ok(Some(Placeholder::Task { name, field }));
+    }
+
+    let (name, default) = match placeholder.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (placeholder, None),
+    };
+    let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_name {
+        return Ok(None);
+    }
+
+    Ok(Some(Placeholder::Var { name, default }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EnvOptions;
+
+    use super::*;
+
+    /// Expands the command of `task` from a `[tasks]` table, with only the tasks' own `env`.
+    fn command(tasks: &str, task: &str) -> anyhow::Result<String> {
+        let tasks: HashMap<String, TaskOptions> = toml::from_str(tasks).unwrap();
+        let root = Path::new("/project");
+        let opts = EnvOptions {
+            clear_env: Some(true),
+            ..EnvOptions::default()
+        };
+        let environment = Environment::load(&opts, root).unwrap();
+        let mut resolver = Resolver::new(&tasks, &environment, root);
+        match resolver
+            .expand_options(task, tasks[task].clone())?
+            .task_options
+        {
+            TaskTypeOptions::Shell(ShellTaskOptions { command }) => Ok(command.to_string()),
+            TaskTypeOptions::Cargo(_) => unreachable!("test tasks are shell tasks"),
+        }
+    }
+
+    #[test]
+    fn expands_variables() {
+        let tasks = r#"a = { command = "echo ${GREETING}, ${NAME}", env = { GREETING = "hi", NAME = "you" } }"#;
+
+        assert_eq!(command(tasks, "a").unwrap(), "echo hi, you");
+    }
+
+    #[test]
+    fn expands_defaults_when_unset_or_empty() {
+        let tasks = r#"
+            a = { command = "echo ${UNSET:-one} ${EMPTY:-two} ${SET:-three}", env = { EMPTY = "", SET = "set" } }
+            "#;
+
+        assert_eq!(command(tasks, "a").unwrap(), "echo one two set");
+    }
+
+    #[test]
+    fn expands_fields_of_other_tasks() {
+        let tasks = r#"
+            db = { command = "postgres -p ${PORT}", cwd = "data", env = { PORT = "5432" } }
+            app = { command = "connect ${task.db.env.PORT} ${task.db.name} ${task.db.cwd} '${task.db.command}'" }
+            "#;
+
+        assert_eq!(
+            command(tasks, "app").unwrap(),
+            "connect 5432 db /project/data 'postgres -p 5432'"
+        );
+    }
+
+    #[test]
+    fn expands_placeholders_in_defaults() {
+        let tasks = r#"
+            db = { command = "postgres", cwd = "data" }
+            a = { command = "echo ${HOST:-${FALLBACK}} ${DIR:-${task.db.cwd}/logs} ${X:-{a,b}} ${SET:-${MISSING}}", env = { FALLBACK = "local", SET = "set" } }
+            "#;
+
+        assert_eq!(
+            command(tasks, "a").unwrap(),
+            "echo local /project/data/logs {a,b} set"
+        );
+    }
+
+    #[test]
+    fn rejects_unclosed_nested_placeholders() {
+        let tasks = r#"a = { command = "echo ${A:-${B}" }"#;
+
+        assert_eq!(
+            command(tasks, "a").unwrap_err().to_string(),
+            "unclosed placeholder in task a: echo ${A:-${B}"
+        );
+    }
+
+    #[test]
+    fn expands_the_root_for_tasks_without_cwd() {
+        let tasks = r#"
+            db = { command = "postgres" }
+            app = { command = "cd ${task.db.cwd}" }
+            "#;
+
+        assert_eq!(command(tasks, "app").unwrap(), "cd /project");
+    }
+
+    #[test]
+    fn leaves_escaped_placeholders_for_the_shell() {
+        let tasks = r#"a = { command = "echo $${HOME} $$ $HOME", env = { HOME = "/home" } }"#;
+
+        assert_eq!(command(tasks, "a").unwrap(), "echo ${HOME} $$ $HOME");
+    }
+
+    #[test]
+    fn leaves_other_expansions_for_the_shell() {
+        let tasks = r#"a = { command = "echo ${f%.*} ${#x} ${x/a/b} ${x:+set} ${1}" }"#;
+
+        assert_eq!(
+            command(tasks, "a").unwrap(),
+            "echo ${f%.*} ${#x} ${x/a/b} ${x:+set} ${1}"
+        );
+    }
+
+    #[test]
+    fn rejects_undefined_variables() {
+        let tasks = r#"a = { command = "echo ${MISSING}" }"#;
+
+        assert_eq!(
+            command(tasks, "a").unwrap_err().to_string(),
+            "undefined variable MISSING in task a: echo ${MISSING}"
+        );
+    }
+
+    #[test]
+    fn rejects_undefined_variables_of_other_tasks() {
+        let tasks = r#"
+            db = { command = "postgres" }
+            app = { command = "echo ${task.db.env.PORT}" }
+            "#;
+
+        assert_eq!(
+            command(tasks, "app").unwrap_err().to_string(),
+            "undefined variable PORT in ${task.db.env.PORT}"
+        );
+    }
+
+    #[test]
+    fn finds_variables() {
+        assert_eq!(
+            variables("${A} $${B} ${C:-c} ${task.a.name} ${#D} $E ${F:-${G:-g}}"),
+            ["A", "C", "F", "G"]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    future,
+    io::{self, IsTerminal},
+    os::unix::io::RawFd,
+    thread,
+};
+
+use nix::{
+    sys::termios::{self, LocalFlags, SetArg, Termios},
+    unistd,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+const STDIN: RawFd = 0;
+
+/// Keyboard input read from concurrently's stdin.
+///
+/// When stdin is a terminal it's switched out of line mode, echo and signal keys for as long as
+/// this is kept, so that each keystroke is passed on as it's typed and the focused task's pty
+/// handles editing, echo, and turning Ctrl-C or Ctrl-Z into signals.
+pub struct Keyboard {
+    rx: UnboundedReceiver<Vec<u8>>,
+    /// Terminal settings to restore.
+    termios: Option<Termios>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        let termios = termios::tcgetattr(STDIN).ok();
+        if let Some(termios) = &termios {
+            let mut raw = termios.clone();
+            raw.local_flags
+                .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG);
+            let _ = termios::tcsetattr(STDIN, SetArg::TCSANOW, &raw);
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        thread::spawn(move || {
+            let mut buf = [0; 1024];
+            while let Ok(n @ 1..) = unistd::read(STDIN, &mut buf) {
+                if tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Keyboard { rx, termios }
+    }
+
+    /// Waits for the next keystrokes, never returning once stdin is closed.
+    pub async fn recv(&mut self) -> Vec<u8> {
+        match self.rx.recv().await {
+            Some(bytes) => bytes,
+            None => future::pending().await,
+        }
+    }
+}
+
+/// Whether stdin is a terminal with concurrently in the foreground, so that reading it won't stop
+/// concurrently with `SIGTTIN`, as it would when run in the background with `&`.
+pub fn in_foreground() -> bool {
+    io::stdin().is_terminal() && unistd::tcgetpgrp(STDIN) == Ok(unistd::getpgrp())
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        if let Some(termios) = &self.termios {
+            let _ = termios::tcsetattr(STDIN, SetArg::TCSANOW, termios);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+
+/// Records where each value of a merged config came from, such as a file or a profile.
+#[derive(Default)]
+pub struct Sources(BTreeMap<String, String>);
+
+impl Sources {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+}
+
+/// Merges `overlay` into `base`, merging nested tables and replacing everything else.
+///
+/// `prefix` is the dotted key of `base` within the whole config, used to record each merged value
+/// as coming from `origin`.
+pub fn merge(
+    base: &mut toml::Table,
+    overlay: toml::Table,
+    prefix: &str,
+    origin: &str,
+    sources: &mut Sources,
+) {
+    for (key, value) in overlay {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge(base, overlay, &path, origin, sources);
+            }
+            (_, value) => {
+                sources
+                    .0
+                    .retain(|key, _| !key.starts_with(&format!("{path}.")));
+                record(&path, &value, origin, sources);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Every value in the table which isn't itself a table, along with its dotted key.
+pub fn leaves(table: &toml::Table) -> Vec<(String, &toml::Value)> {
+    fn collect<'a>(
+        prefix: &str,
+        table: &'a toml::Table,
+        leaves: &mut Vec<(String, &'a toml::Value)>,
+    ) {
+        for (key, value) in table {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{prefix}.{key}")
+            };
+            match value {
+                toml::Value::Table(table) => collect(&path, table, leaves),
+                value => leaves.push((path, value)),
+            }
+        }
+    }
+
+    let mut leaves = Vec::new();
+    collect("", table, &mut leaves);
+    leaves
+}
+
+fn record(path: &str, value: &toml::Value, origin: &str, sources: &mut Sources) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                record(&format!("{path}.{key}"), value, origin, sources);
+            }
+        }
+        _ => {
+            sources.0.insert(path.to_string(), origin.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Merges each of `layers` in turn into an empty table, as coming from the file named by its
+    /// index, and returns the merged table and the source of every leaf.
+    fn merge_layers(layers: &[&str]) -> (toml::Table, Vec<(String, String)>) {
+        let mut table = toml::Table::new();
+        let mut sources = Sources::default();
+        for (i, layer) in layers.iter().enumerate() {
+            let overlay = toml::from_str(layer).unwrap();
+            merge(&mut table, overlay, "", &format!("file{i}"), &mut sources);
+        }
+        let leaves = leaves(&table)
+            .into_iter()
+            .map(|(key, _)| {
+                let source = sources.get(&key).unwrap_or("unknown").to_string();
+                (key, source)
+            })
+            .collect();
+        (table, leaves)
+    }
+
+    /// Layers merged in order, the merged table, and the source of each of its leaves.
+    type Case<'a> = (&'a [&'a str], &'a str, &'a [(&'a str, &'a str)]);
+
+    #[test]
+    fn merges_layers() {
+        let cases: &[Case] = &[
+            (
+                &["a = 1\nb = 2", "b = 3"],
+                "a = 1\nb = 3",
+                &[("a", "file0"), ("b", "file1")],
+            ),
+            (
+                &[
+                    "[tasks.web]\ncommand = 'serve'\nenv = { A = '1', B = '2' }",
+                    "[tasks.web.env]\nB = '3'",
+                ],
+                "[tasks.web]\ncommand = 'serve'\nenv = { A = '1', B = '3' }",
+                &[
+                    ("tasks.web.command", "file0"),
+                    ("tasks.web.env.A", "file0"),
+                    ("tasks.web.env.B", "file1"),
+                ],
+            ),
+            (
+                &["args = ['a', 'b']", "args = ['c']"],
+                "args = ['c']",
+                &[("args", "file1")],
+            ),
+            (
+                &["[watch]\npaths = ['src']\ndelay = 1", "watch = false"],
+                "watch = false",
+                &[("watch", "file1")],
+            ),
+            (
+                &["watch = false", "[watch]\npaths = ['src']"],
+                "[watch]\npaths = ['src']",
+                &[("watch.paths", "file1")],
+            ),
+            (
+                &["[a]\nb = 1", "[a]\nc = 2", "[a]\nb = 3"],
+                "[a]\nb = 3\nc = 2",
+                &[("a.b", "file2"), ("a.c", "file1")],
+            ),
+        ];
+
+        for (layers, expected, expected_sources) in cases {
+            let (table, sources) = merge_layers(layers);
+            let expected: toml::Table = toml::from_str(expected).unwrap();
+            let expected_sources: Vec<_> = expected_sources
+                .iter()
+                .map(|(key, source)| (key.to_string(), source.to_string()))
+                .collect();
+            assert_eq!(table, expected, "{layers:?}");
+            assert_eq!(sources, expected_sources, "{layers:?}");
+        }
+    }
+
+    #[test]
+    fn clears_sources_of_replaced_tables() {
+        let mut table = toml::Table::new();
+        let mut sources = Sources::default();
+        let layers = ["[watch]\npaths = ['src']", "watch = false"];
+        for (i, layer) in layers.iter().enumerate() {
+            let overlay = toml::from_str(layer).unwrap();
+            merge(&mut table, overlay, "", &format!("file{i}"), &mut sources);
+        }
+
+        assert_eq!(sources.get("watch"), Some("file1"));
+        assert_eq!(sources.get("watch.paths"), None);
+    }
+}
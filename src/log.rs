@@ -1,67 +1,39 @@
-use std::{fmt, process};
+use std::fmt;
 
 use colored::Colorize;
 
-pub trait UnwrapResult<T> {
-    fn unwrap_error(self, msg: impl fmt::Display) -> T;
-    fn unwrap_warn(self, msg: impl fmt::Display) -> T;
-}
-
-impl<T, E> UnwrapResult<T> for Result<T, E> {
-    fn unwrap_error(self, msg: impl fmt::Display) -> T {
-        match self {
-            Ok(val) => val,
-            Err(_) => {
-                error(msg);
-                process::exit(1)
-            }
-        }
-    }
-
-    fn unwrap_warn(self, msg: impl fmt::Display) -> T {
-        match self {
-            Ok(val) => val,
-            Err(_) => {
-                warn(msg);
-                process::exit(0)
-            }
-        }
-    }
-}
-
-impl<T> UnwrapResult<T> for Option<T> {
-    fn unwrap_error(self, msg: impl fmt::Display) -> T {
-        match self {
-            Some(val) => val,
-            None => {
-                error(msg);
-                process::exit(1)
-            }
-        }
-    }
-
-    fn unwrap_warn(self, msg: impl fmt::Display) -> T {
-        match self {
-            Some(val) => val,
-            None => {
-                warn(msg);
-                process::exit(0)
-            }
-        }
-    }
-}
+use crate::{
+    events::{self, Event, Level},
+    output,
+};
 
 pub fn info(msg: impl fmt::Display) {
+    if events::enabled() {
+        return log(Level::Info, msg);
+    }
     let tag = "[info]:".bold().blue();
-    println!("{} {}", tag, msg);
+    output::message(format!("{} {}", tag, msg));
 }
 
 pub fn error(msg: impl fmt::Display) {
+    if events::enabled() {
+        return log(Level::Error, msg);
+    }
     let tag = "[error]:".bold().red();
-    println!("{} {}", tag, msg);
+    output::message(format!("{} {}", tag, msg));
 }
 
 pub fn warn(msg: impl fmt::Display) {
+    if events::enabled() {
+        return log(Level::Warn, msg);
+    }
     let tag = "[warn]:".bold().yellow();
-    println!("{} {}", tag, msg);
+    output::message(format!("{} {}", tag, msg));
+}
+
+fn log(level: Level, msg: impl fmt::Display) {
+    events::emit(Event::Log {
+        level,
+        message: output::strip_ansi(&msg.to_string()),
+    });
 }
@@ -0,0 +1,267 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{config::LogFile, log::warn, output};
+
+/// Format of the time each line starts with, when `timestamps` is set.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Saves a task's output to its log file.
+///
+/// Lines are written on a thread of their own, so a slow disk never holds up the task's output
+/// on the console. Clones write to the same file.
+#[derive(Clone)]
+pub struct Log {
+    tx: Sender<(SystemTime, String)>,
+}
+
+impl Log {
+    /// Opens the file at `path` for appending and starts writing lines to it.
+    pub fn start(task: &str, path: PathBuf, opts: LogFile) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let writer = Writer {
+            task: task.to_string(),
+            path,
+            opts,
+            file: None,
+            size: 0,
+        };
+        thread::spawn(move || writer.run(rx));
+        Log { tx }
+    }
+
+    pub fn write(&self, line: &str) {
+        let _ = self.tx.send((SystemTime::now(), line.to_string()));
+    }
+}
+
+struct Writer {
+    task: String,
+    path: PathBuf,
+    opts: LogFile,
+    file: Option<BufWriter<File>>,
+    /// Bytes in the current file.
+    size: u64,
+}
+
+impl Writer {
+    /// Writes lines until every [`Log`] for the file is dropped, flushing whenever none are
+    /// waiting.
+    fn run(mut self, rx: Receiver<(SystemTime, String)>) {
+        if let Err(err) = self.open() {
+            warn(format!(
+                "failed to open log file {} for task {}: {err}",
+                self.path.display(),
+                self.task
+            ));
+            return;
+        }
+
+        while let Ok(line) = rx.recv() {
+            let mut result = self.write(line);
+            while let Ok(line) = rx.try_recv() {
+                result = result.and_then(|()| self.write(line));
+            }
+            if let Some(file) = &mut self.file {
+                result = result.and_then(|()| file.flush());
+            }
+            if let Err(err) = result {
+                warn(format!(
+                    "failed to write log file {} for task {}: {err}",
+                    self.path.display(),
+                    self.task
+                ));
+                return;
+            }
+        }
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::options().create(true).append(true).open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    fn write(&mut self, (time, line): (SystemTime, String)) -> io::Result<()> {
+        let mut line = if self.opts.strip_ansi {
+            output::strip_ansi(&line)
+        } else {
+            line
+        };
+        if self.opts.timestamps {
+            let time = DateTime::<Local>::from(time).format(TIMESTAMP_FORMAT);
+            line = format!("[{time}] {line}");
+        }
+        line.push('\n');
+
+        let len = line.len() as u64;
+        if self
+            .opts
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + len > max_size)
+        {
+            self.rotate()?;
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(line.as_bytes())?;
+            self.size += len;
+        }
+        Ok(())
+    }
+
+    /// Moves the current file to `<path>.1`, and older ones up by one, dropping any beyond
+    /// `max_files`, then starts a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let max_files = self.opts.max_files;
+        if max_files == 0 {
+            remove(&self.path)?;
+        } else {
+            remove(&rotated(&self.path, max_files))?;
+            for n in (1..max_files).rev() {
+                rename(&rotated(&self.path, n), &rotated(&self.path, n + 1))?;
+            }
+            rename(&self.path, &rotated(&self.path, 1))?;
+        }
+        self.open()
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{n}"));
+    path.into()
+}
+
+/// Removes a file, if it exists.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Renames a file, if it exists.
+fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    /// Writes `lines` to `app.log` in a new directory, rotating it at 10 bytes, and returns the
+    /// directory along with the contents of `app.log` and `app.log.1` to `app.log.3`.
+    fn rotate(name: &str, max_files: usize, lines: &[&str]) -> (PathBuf, Vec<Option<String>>) {
+        let dir = std::env::temp_dir().join(format!("concurrently-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("app.log");
+        let mut writer = Writer {
+            task: "app".to_string(),
+            path: path.clone(),
+            opts: LogFile {
+                path: String::new(),
+                strip_ansi: false,
+                timestamps: false,
+                max_size: Some(10),
+                max_files,
+            },
+            file: None,
+            size: 0,
+        };
+        writer.open().unwrap();
+        for line in lines {
+            writer.write((SystemTime::now(), line.to_string())).unwrap();
+        }
+        writer.file.take().unwrap().flush().unwrap();
+
+        let files = (0..=3)
+            .map(|n| {
+                let path = if n == 0 {
+                    path.clone()
+                } else {
+                    rotated(&path, n)
+                };
+                fs::read_to_string(path).ok()
+            })
+            .collect();
+        (dir, files)
+    }
+
+    fn contents(files: &[&str]) -> Vec<Option<String>> {
+        files
+            .iter()
+            .map(|file| (!file.is_empty()).then(|| file.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rotates_at_max_size() {
+        let (dir, files) = rotate("rotate", 5, &["aaaa", "bbbb", "cccc"]);
+
+        assert_eq!(files, contents(&["cccc\n", "aaaa\nbbbb\n", "", ""]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn shifts_rotated_files_up() {
+        let (dir, files) = rotate(
+            "shift",
+            5,
+            &["aaaa", "bbbb", "cccc", "dddd", "eeee", "ffff"],
+        );
+
+        assert_eq!(
+            files,
+            contents(&["eeee\nffff\n", "cccc\ndddd\n", "aaaa\nbbbb\n", ""])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_files_beyond_max_files() {
+        let lines = ["aaaa", "bbbb", "cccc", "dddd", "eeee", "ffff", "gggg"];
+        let (dir, files) = rotate("max-files", 2, &lines);
+
+        assert_eq!(
+            files,
+            contents(&["gggg\n", "eeee\nffff\n", "cccc\ndddd\n", ""])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncates_without_rotated_files() {
+        let (dir, files) = rotate("no-files", 0, &["aaaa", "bbbb", "cccc"]);
+
+        assert_eq!(files, contents(&["cccc\n", "", "", ""]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_lines_longer_than_max_size() {
+        let (dir, files) = rotate("long", 5, &["a long line", "aaaa"]);
+
+        assert_eq!(files, contents(&["aaaa\n", "a long line\n", "", ""]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,20 +1,55 @@
-use std::{process, sync::Arc, time::Duration};
-
-use anyhow::Context;
-use clap::Parser;
-use colored::Color;
-use config::Config;
-use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{
+    io::{self, IsTerminal},
+    path::PathBuf,
+    process,
+    sync::Arc,
+};
+
+use anyhow::bail;
+use clap::{Parser, Subcommand};
+use colored::{Color, Colorize};
+use config::{ExitCode, OutputMode, Success, TaskTypeOptions};
+use events::Event;
+use futures::{stream::FuturesUnordered, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use log::{error, info};
-use tokio::{fs, io, signal, time};
+use prefix::{Prefix, Template, Widths};
+use socket::Request;
+use tokio::{
+    signal,
+    time::{self, Duration, Instant},
+};
 
-use crate::{log::warn, task::Task};
+use crate::{
+    discover::ConfigFile, env::Environment, graph::TaskGraph, interpolate::Resolver,
+    profile::DEFAULT_PROFILE, project::Project, runner::Runner, task::Task,
+};
 
+mod check;
+mod commands;
 mod config;
+mod discover;
+mod env;
+mod events;
 mod fake_tty;
+mod forward;
+mod graph;
+mod interpolate;
+mod keyboard;
+mod layer;
 mod log;
+mod log_file;
+mod output;
+mod prefix;
+mod profile;
+mod project;
+mod ready;
+mod runner;
+mod socket;
+mod summary;
 mod task;
+mod tui;
+mod watch;
 
 const COLORS: [Color; 10] = [
     Color::Green,
@@ -36,6 +71,60 @@ struct Args {
     /// Tasks to run
     #[clap(global = true)]
     tasks: Vec<String>,
+
+    /// Path to a tasks.toml or Cargo.toml, instead of searching for one
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Profile to run, selecting its tasks and applying its overrides
+    #[clap(long)]
+    profile: Option<String>,
+
+    /// Print the merged config along with where each value came from, then exit
+    #[clap(long)]
+    sources: bool,
+
+    /// Stop all other tasks once any task exits for good
+    #[clap(long)]
+    kill_others: bool,
+
+    /// Stop all other tasks once any task fails for good
+    #[clap(long)]
+    kill_others_on_fail: bool,
+
+    /// Tasks which must succeed for the run to succeed: all, first, last or a task name
+    #[clap(long, value_name = "CONDITION")]
+    success: Option<Success>,
+
+    /// Which failed task the exit code is taken from
+    #[clap(long, value_enum)]
+    exit_code: Option<ExitCode>,
+
+    /// How task output is printed, for every task
+    #[clap(long, value_enum, value_name = "MODE")]
+    output: Option<OutputMode>,
+
+    /// What comes before each line of output, such as "[{time}] {name}"
+    #[clap(long, value_name = "TEMPLATE")]
+    prefix: Option<Template>,
+
+    /// Show the tasks in a full screen TUI, with a pane of output for each task
+    #[clap(long)]
+    tui: bool,
+
+    #[clap(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Check the config and the given tasks for problems, without running anything
+    Check,
+    /// Control a session running in this project from another terminal
+    Ctl {
+        #[clap(subcommand)]
+        request: Request,
+    },
 }
 
 #[tokio::main]
@@ -48,7 +137,7 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
 
-    let selected_tasks: Vec<_> = args
+    let mut selected_tasks: Vec<_> = args
         .tasks
         .into_iter()
         .flat_map(|member_string| {
@@ -59,59 +148,153 @@ async fn main() -> anyhow::Result<()> {
         })
         .collect();
 
-    let tasks_file = fs::read_to_string("./tasks.toml")
-        .await
-        .context("no tasks.toml found")?;
-    let config: Config = toml::from_str(&tasks_file)?;
+    let mut config_file = discover::load(args.config.as_deref())?;
+    let root = config_file.root();
+    let socket_path = socket::path(&root);
 
-    if let Some(env) = config.env {
-        match dotenv::from_filename(&env) {
-            Ok(_) => {
-                info(format!("loaded env file {}", env));
-            }
-            Err(_) => {
-                warn(format!("failed to load env file {}", env));
-            }
+    if let Some(Commands::Ctl { request }) = &args.command {
+        return socket::ctl(&socket_path, request.clone()).await;
+    }
+
+    let profile = args.profile.or_else(|| {
+        (selected_tasks.is_empty() && profile::exists(&config_file.table, DEFAULT_PROFILE))
+            .then(|| DEFAULT_PROFILE.to_string())
+    });
+
+    // Decided before the config is checked, so that its problems are reported as events too.
+    let output = args.output.unwrap_or_else(|| {
+        config_file
+            .table
+            .get("output")
+            .and_then(|output| output.clone().try_into().ok())
+            .unwrap_or_default()
+    });
+    if output == OutputMode::Json {
+        if args.tui {
+            bail!("--tui can't be used with json output");
         }
+        events::enable();
     }
 
-    let mut tasks: Vec<_> = config
-        .tasks
-        .into_iter()
-        .filter(|task| {
-            if selected_tasks.is_empty() {
-                true
-            } else {
-                selected_tasks
-                    .iter()
-                    .any(|selected_task| *selected_task == task.0)
-            }
+    let diagnostics = check::check(
+        &config_file,
+        &selected_tasks,
+        profile.as_deref(),
+        args.success.as_ref(),
+    );
+    let errors = check::report(&diagnostics);
+    if errors > 0 {
+        error(format!("found {errors} problem(s) in the config"));
+        process::exit(1);
+    }
+    if matches!(args.command, Some(Commands::Check)) {
+        info("config is valid");
+        return Ok(());
+    }
+
+    if let Some(profile) = profile {
+        let profile_tasks = profile::apply(&mut config_file, &profile)?;
+        if selected_tasks.is_empty() {
+            selected_tasks = profile_tasks;
+        }
+    }
+
+    if args.tui && !io::stdout().is_terminal() {
+        bail!("--tui needs stdout to be a terminal");
+    }
+
+    if args.sources {
+        print_sources(&config_file);
+        return Ok(());
+    }
+
+    let mut config = config_file.parse()?;
+    config.exit.kill_others |= args.kill_others;
+    config.exit.kill_others_on_fail |= args.kill_others_on_fail;
+    if let Some(success) = args.success {
+        config.exit.success = success;
+    }
+    if let Some(exit_code) = args.exit_code {
+        config.exit.exit_code = exit_code;
+    }
+    let environment = Environment::load(&config.env, &root)?;
+
+    let graph = TaskGraph::new(&config.tasks)?;
+    let included = if selected_tasks.is_empty() {
+        None
+    } else {
+        Some(graph.with_dependencies(selected_tasks.iter().map(String::as_str)))
+    };
+
+    let tasks: Vec<_> = graph
+        .order()
+        .iter()
+        .filter(|name| {
+            included
+                .as_ref()
+                .is_none_or(|included| included.contains(*name))
         })
+        .filter_map(|name| Some((name.clone(), config.tasks.get(name)?.clone())))
         .collect();
-    tasks.sort_by(|(a, _), (b, _)| a.cmp(b));
-    let longest_name = tasks.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
-    let mut next_color: usize = 0;
+    let has_cargo_tasks = tasks
+        .iter()
+        .any(|(_, opts)| matches!(opts.task_options, TaskTypeOptions::Cargo(_)));
+    let project = Project::new(root, has_cargo_tasks).await?;
 
+    let template = Arc::new(args.prefix.unwrap_or(config.prefix));
+    let widths = Widths::new(tasks.iter().map(|(name, _)| name.as_str()));
+    let mut resolver = Resolver::new(&config.tasks, &environment, &project.root);
     let tasks: Vec<_> = tasks
         .into_iter()
-        .filter_map(|(name, opts)| {
-            let color = COLORS[next_color];
-            next_color += 1;
-            if next_color > 10 {
-                next_color = 0;
+        .enumerate()
+        .map(|(i, (name, opts))| {
+            let color = COLORS[i % COLORS.len()];
+            let env = resolver.env(&name)?;
+            let mut opts = resolver.expand_options(&name, opts)?;
+            opts.output = args.output.or(opts.output).or(Some(config.output));
+            opts.log_file = opts.log_file.or_else(|| config.log_file.clone());
+            let prefix = Prefix::new(template.clone(), name.clone(), i, color, widths);
+            let task = Task::from_options(name, color, prefix, opts, env, &project);
+            if !task.cwd.is_dir() {
+                bail!(
+                    "working directory {} for task {} does not exist",
+                    task.cwd.display(),
+                    task.name
+                );
             }
-            let tag_padding = longest_name - name.len();
-            Some(Task::from_options(name, color, tag_padding, opts))
+            Ok(task)
         })
-        .collect();
+        .collect::<anyhow::Result<_>>()?;
 
     if tasks.is_empty() {
         info("nothing to run");
         return Ok(());
     }
 
+    let prepared = prepare(&tasks).await;
+
+    let mut runner = Runner::new(tasks, config.exit, prepared);
+    runner = runner.with_socket(socket_path);
+    if args.tui {
+        runner = runner.with_tui();
+    }
+    let code = runner.run().await;
+    if code != 0 {
+        process::exit(code);
+    }
+
+    Ok(())
+}
+
+/// Runs each task's prepare step concurrently, exiting if any of them fail.
+///
+/// Returns how long each task took to prepare, if it had anything to prepare.
+async fn prepare(tasks: &[Task]) -> Vec<Option<Duration>> {
     let m = MultiProgress::new();
     m.set_move_cursor(true);
+    if events::enabled() {
+        m.set_draw_target(ProgressDrawTarget::hidden());
+    }
     let sty =
         ProgressStyle::with_template("{prefix} {spinner:.bold/white} {wide_msg:.bold/white/!}")
             .unwrap();
@@ -120,9 +303,7 @@ async fn main() -> anyhow::Result<()> {
         tasks
             .iter()
             .map(|task| {
-                let spinner = m
-                    .add(ProgressBar::new_spinner())
-                    .with_prefix(task.tag.clone());
+                let spinner = m.add(ProgressBar::new_spinner()).with_prefix(task.tag());
                 spinner.set_style(sty.clone());
                 spinner
             })
@@ -145,7 +326,19 @@ async fn main() -> anyhow::Result<()> {
     for (i, task) in tasks.iter().enumerate() {
         let spinner = spinners[i].clone();
         workers.push(async move {
-            let result = task.prepare(spinner.clone()).await;
+            if task.has_prepare() {
+                events::emit(Event::PrepareStart { task: &task.name });
+            }
+            let start = Instant::now();
+            let result = task.prepare(Some(spinner.clone())).await;
+            if let Some(result) = &result {
+                events::emit(Event::PrepareEnd {
+                    task: &task.name,
+                    success: result.as_ref().is_ok_and(|status| status.success()),
+                    exit: result.as_ref().ok().map(|status| (*status).into()),
+                    duration: start.elapsed().as_secs_f64(),
+                });
+            }
             match &result {
                 Some(Ok(status)) => {
                     if status.success() {
@@ -170,71 +363,54 @@ async fn main() -> anyhow::Result<()> {
                     spinner.finish_and_clear();
                 }
             }
-            result
+            (i, result, start.elapsed())
         });
     }
-    while let Some(result) = workers.next().await {
+    let mut durations = vec![None; tasks.len()];
+    // Prepare commands run in process groups of their own, so Ctrl-C doesn't reach them directly.
+    // Dropping the workers kills them before exiting.
+    let ctrl_c = signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let (i, result, duration) = tokio::select! {
+            worker = workers.next() => match worker {
+                Some(worker) => worker,
+                None => break,
+            },
+            _ = &mut ctrl_c => {
+                drop(workers);
+                process::exit(130);
+            }
+        };
         if let Some(result) = result {
-            if !result.map(|status| status.success()).unwrap_or(false) {
-                error("task preparation failed");
-                process::exit(1);
+            match result {
+                Ok(status) if status.success() => durations[i] = Some(duration),
+                result => {
+                    drop(workers);
+                    error("task preparation failed");
+                    let code = result.ok().and_then(|status| status.code());
+                    process::exit(code.filter(|code| *code != 0).unwrap_or(1));
+                }
             }
         }
     }
 
     ticker.abort();
     let _ = m.clear();
+    durations
+}
 
-    let mut workers = FuturesUnordered::new();
-    for task in &tasks {
-        let task = task.clone();
-        workers.push(
-            async move {
-                let status = task.run().await?;
-                Result::<_, io::Error>::Ok((status, task))
-            }
-            .boxed(),
-        );
-    }
-
-    loop {
-        tokio::select! {
-            Some(result) = workers.next() => {
-                if let Ok((status, mut task)) = result {
-                    if !status.success() {
-                        if task.retries > task.max_retries {
-                            error(format!(
-                                "task {} exited with non-success code too many times, exiting.",
-                                task.name
-                            ));
-                            return Ok(());
-                        }
-                        let sleep_secs = (task.retries + 1) as u64;
-                        warn(format!(
-                            "task exited with non-success code, retrying again in {} seconds...",
-                            sleep_secs
-                        ));
-                        let task = async move {
-                            tokio::spawn(async move {
-                                time::sleep(Duration::from_secs(sleep_secs)).await;
-                                task.retries += 1;
-                                let status = task.run().await?;
-                                Result::<_, io::Error>::Ok((status, task))
-                            }).await?
-                        }.boxed();
-                        workers.push(task);
-                    }
-                }
-            }
-            _ = signal::ctrl_c() => {
-                println!("Shutting down...");
-                break;
-            }
-        }
-        if workers.is_empty() {
-            break;
+fn print_sources(config_file: &ConfigFile) {
+    for (key, value) in layer::leaves(&config_file.table) {
+        let source = config_file.sources.get(&key).unwrap_or("unknown");
+        if events::enabled() {
+            events::emit(Event::Source {
+                key: &key,
+                value: value.to_string(),
+                source,
+            });
+        } else {
+            println!("{key} = {value} {}", format!("# {source}").dimmed());
         }
     }
-
-    Ok(())
 }
@@ -0,0 +1,222 @@
+use std::{
+    fmt,
+    io::{self, IsTerminal, Write},
+    sync::{LazyLock, Mutex},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::events;
+
+/// Set while output is captured by the TUI rather than printed.
+static CAPTURE: Mutex<Option<UnboundedSender<Output>>> = Mutex::new(None);
+
+/// Receivers of all output, whether it's printed or captured.
+static TAPS: Mutex<Vec<UnboundedSender<Output>>> = Mutex::new(Vec::new());
+
+/// Task whose unfinished line was printed last, so the cursor is still at the end of it.
+static UNFINISHED: Mutex<Option<String>> = Mutex::new(None);
+
+/// Whether unfinished lines can be printed and then redrawn in place.
+static REDRAW: LazyLock<bool> = LazyLock::new(|| io::stdout().is_terminal());
+
+/// Escape sequences for colors, cursor movement and terminal titles.
+static ANSI: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[@-_])").unwrap()
+});
+
+/// Output captured while the TUI is running, or sent to taps.
+#[derive(Clone)]
+pub enum Output {
+    /// A line of a task's output, or a message about the task.
+    Line { task: String, line: String },
+    /// A task's unfinished line so far, which its next line or partial line replaces.
+    Partial { task: String, line: String },
+    /// A message from concurrently itself.
+    Message(String),
+    /// A task's status changed.
+    Phase { task: String, phase: Phase },
+}
+
+/// Status of a task as shown to the user.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Pending,
+    Starting,
+    Running,
+    Restarting,
+    Exited,
+    Failed,
+    Stopped,
+    Skipped,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Pending => "pending",
+            Phase::Starting => "starting",
+            Phase::Running => "running",
+            Phase::Restarting => "restarting",
+            Phase::Exited => "exited",
+            Phase::Failed => "failed",
+            Phase::Stopped => "stopped",
+            Phase::Skipped => "skipped",
+        }
+    }
+}
+
+/// Captures all output until [`release`] is called, instead of printing it.
+pub fn capture() -> UnboundedReceiver<Output> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    *CAPTURE.lock().unwrap() = Some(tx);
+    rx
+}
+
+/// Goes back to printing output.
+pub fn release() {
+    *CAPTURE.lock().unwrap() = None;
+}
+
+/// Receives all output from now on, alongside it being printed or captured.
+pub fn tap() -> UnboundedReceiver<Output> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    TAPS.lock().unwrap().push(tx);
+    rx
+}
+
+/// Removes escape sequences from a line, leaving its plain text.
+pub fn strip_ansi(line: &str) -> String {
+    ANSI.replace_all(line, "").into_owned()
+}
+
+/// Prints a line of a task's output, or a message about the task, after its tag.
+pub fn task(name: &str, tag: &str, line: impl fmt::Display) {
+    send(
+        || Output::Line {
+            task: name.to_string(),
+            line: line.to_string(),
+        },
+        || {
+            finish(Some(name));
+            println!("{tag} {line}");
+        },
+    );
+}
+
+/// Prints a task's unfinished line so far, after its tag if it has one, redrawing it in place
+/// as more of it comes in.
+///
+/// This is only printed when stdout is a terminal, since elsewhere it couldn't be redrawn and
+/// the finished line is printed anyway.
+pub fn partial(name: &str, tag: Option<&str>, line: &str) {
+    send(
+        || Output::Partial {
+            task: name.to_string(),
+            line: line.to_string(),
+        },
+        || {
+            if !*REDRAW {
+                return;
+            }
+            finish(Some(name));
+            let mut stdout = io::stdout().lock();
+            let _ = match tag {
+                Some(tag) => write!(stdout, "{tag} {line}"),
+                None => write!(stdout, "{line}"),
+            };
+            let _ = stdout.flush();
+            *UNFINISHED.lock().unwrap() = Some(name.to_string());
+        },
+    );
+}
+
+/// Prints a line of a task's output as it is, without its tag.
+pub fn raw(name: &str, line: impl fmt::Display) {
+    send(
+        || Output::Line {
+            task: name.to_string(),
+            line: line.to_string(),
+        },
+        || {
+            finish(Some(name));
+            println!("{line}");
+        },
+    );
+}
+
+/// Prints lines of a task's output after its tag, without other output coming between them.
+pub fn group(name: &str, tag: &str, lines: &[String]) {
+    let capture = CAPTURE.lock().unwrap();
+    for line in lines {
+        let output = || Output::Line {
+            task: name.to_string(),
+            line: line.clone(),
+        };
+        TAPS.lock()
+            .unwrap()
+            .retain(|tap| tap.send(output()).is_ok());
+        match &*capture {
+            Some(tx) => {
+                let _ = tx.send(output());
+            }
+            None if events::enabled() => {}
+            None => {
+                finish(None);
+                println!("{tag} {line}");
+            }
+        }
+    }
+}
+
+/// Prints a message from concurrently itself.
+pub fn message(line: impl fmt::Display) {
+    send(
+        || Output::Message(line.to_string()),
+        || {
+            finish(None);
+            println!("{line}");
+        },
+    );
+}
+
+/// Gets the cursor to the start of a line before printing, clearing the unfinished line printed
+/// last if it's about to be redrawn by the same task, or else moving past it.
+fn finish(name: Option<&str>) {
+    let Some(unfinished) = UNFINISHED.lock().unwrap().take() else {
+        return;
+    };
+    if name == Some(unfinished.as_str()) {
+        print!("\r\x1b[2K");
+    } else {
+        println!();
+    }
+}
+
+/// Reports a task's status, which is only shown by the TUI.
+pub fn phase(name: &str, phase: Phase) {
+    send(
+        || Output::Phase {
+            task: name.to_string(),
+            phase,
+        },
+        || {},
+    );
+}
+
+fn send(captured: impl Fn() -> Output, print: impl FnOnce()) {
+    TAPS.lock()
+        .unwrap()
+        .retain(|tap| tap.send(captured()).is_ok());
+    match &*CAPTURE.lock().unwrap() {
+        Some(tx) => {
+            let _ = tx.send(captured());
+        }
+        // Events take the place of text.
+        None if events::enabled() => {}
+        None => print(),
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    fmt::Write,
+    fs,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    Local,
+};
+use colored::{Color, Colorize};
+use serde::{de, Deserialize};
+
+use crate::summary;
+
+/// Format of `{time}` when it isn't given one.
+const DEFAULT_TIME_FORMAT: &str = "%H:%M:%S";
+
+/// Width of `{elapsed}`, which fits any time under an hour.
+const ELAPSED_WIDTH: usize = 6;
+
+/// Width of `{restarts}`.
+const RESTARTS_WIDTH: usize = 3;
+
+/// Template for what comes before each line of a task's output, such as `[{time}] {name}`.
+#[derive(Clone, Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Name,
+    Index,
+    Pid,
+    /// Wall-clock time, in a `strftime` format.
+    Time(Vec<Item<'static>>),
+    /// Time since the task's current run started.
+    Elapsed,
+    Restarts,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template {
+            parts: vec![Part::Name],
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed {{ in prefix {s:?}"))?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::new(&rest[..end])?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err(format!(
+                        "unmatched }} in prefix {s:?}, use }}}} for a literal }}"
+                    ))
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Part {
+    fn new(placeholder: &str) -> Result<Self, String> {
+        let (name, format) = match placeholder.split_once(':') {
+            Some((name, format)) => (name, Some(format)),
+            None => (placeholder, None),
+        };
+        let part = match (name, format) {
+            ("name", None) => Part::Name,
+            ("index", None) => Part::Index,
+            ("pid", None) => Part::Pid,
+            ("time", format) => {
+                let format = StrftimeItems::new(format.unwrap_or(DEFAULT_TIME_FORMAT))
+                    .parse_to_owned()
+                    .map_err(|_| format!("invalid time format in {{{placeholder}}}"))?;
+                Part::Time(format)
+            }
+            ("elapsed", None) => Part::Elapsed,
+            ("restarts", None) => Part::Restarts,
+            _ => {
+                return Err(format!(
+                    "unknown placeholder {{{placeholder}}}, expected name, index, pid, \
+                     time, time:<format>, elapsed or restarts"
+                ))
+            }
+        };
+
+        Ok(part)
+    }
+}
+
+/// Renders a task's prefix for each line, from the template and the task's current run.
+///
+/// Clones share the current run, so the runner can update it for every copy of the task.
+#[derive(Clone)]
+pub struct Prefix {
+    template: Arc<Template>,
+    name: String,
+    index: usize,
+    color: Color,
+    run: Arc<Mutex<Run>>,
+    widths: Widths,
+}
+
+/// Widths which `{name}`, `{index}` and `{pid}` are padded to, so that every task's prefix lines
+/// up and stays the same width from one line to the next.
+#[derive(Clone, Copy)]
+pub struct Widths {
+    pub name: usize,
+    pub index: usize,
+    pub pid: usize,
+}
+
+impl Widths {
+    /// Widths for the names of all the tasks in the run.
+    pub fn new<'a>(names: impl ExactSizeIterator<Item = &'a str>) -> Self {
+        // The largest pid is one less than pid_max, which is at most 2^22.
+        let pid_max = fs::read_to_string("/proc/sys/kernel/pid_max")
+            .ok()
+            .and_then(|pid_max| pid_max.trim().parse::<u32>().ok())
+            .unwrap_or(1 << 22);
+        Widths {
+            index: names.len().saturating_sub(1).to_string().len(),
+            name: names.map(|name| name.chars().count()).max().unwrap_or(0),
+            pid: (pid_max - 1).to_string().len(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Run {
+    pid: Option<u32>,
+    started: Option<Instant>,
+    restarts: usize,
+}
+
+impl Prefix {
+    pub fn new(
+        template: Arc<Template>,
+        name: String,
+        index: usize,
+        color: Color,
+        widths: Widths,
+    ) -> Self {
+        Prefix {
+            template,
+            name,
+            index,
+            color,
+            run: Arc::default(),
+            widths,
+        }
+    }
+
+    /// Starts a new run, with the number of restarts before it.
+    pub fn starting(&self, restarts: usize) {
+        *self.run.lock().unwrap() = Run {
+            restarts,
+            ..Run::default()
+        };
+    }
+
+    /// Records the run's process, which elapsed time is counted from.
+    pub fn spawned(&self, pid: Option<u32>) {
+        let mut run = self.run.lock().unwrap();
+        run.pid = pid;
+        run.started = Some(Instant::now());
+    }
+
+    /// The prefix followed by a divider.
+    pub fn render(&self) -> String {
+        format!(
+            "{}  {} ",
+            self.text().bold().color(self.color),
+            "|".bold().color(self.color),
+        )
+    }
+
+    fn text(&self) -> String {
+        let run = self.run.lock().unwrap();
+        let Widths { name, index, pid } = self.widths;
+        let mut text = String::new();
+        for part in &self.template.parts {
+            let _ = match part {
+                Part::Text(s) => write!(text, "{s}"),
+                Part::Name => write!(text, "{:<name$}", self.name),
+                Part::Index => write!(text, "{:>index$}", self.index),
+                Part::Pid => match run.pid {
+                    Some(run_pid) => write!(text, "{run_pid:>pid$}"),
+                    None => write!(text, "{:>pid$}", "-"),
+                },
+                Part::Time(format) => {
+                    write!(text, "{}", Local::now().format_with_items(format.iter()))
+                }
+                Part::Elapsed => match run.started {
+                    Some(started) => write!(
+                        text,
+                        "{:>ELAPSED_WIDTH$}",
+                        summary::duration(started.elapsed())
+                    ),
+                    None => write!(text, "{:>ELAPSED_WIDTH$}", "-"),
+                },
+                Part::Restarts => write!(text, "{:>RESTARTS_WIDTH$}", run.restarts),
+            };
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn prefix(template: &str) -> Prefix {
+        Prefix::new(
+            Arc::new(template.parse().unwrap()),
+            "web".to_string(),
+            1,
+            Color::Green,
+            Widths {
+                name: 6,
+                index: 2,
+                pid: 7,
+            },
+        )
+    }
+
+    fn error(template: &str) -> String {
+        template.parse::<Template>().unwrap_err()
+    }
+
+    #[test]
+    fn renders_placeholders_and_text() {
+        let prefix = prefix("[{index}] {name}:{restarts}");
+        prefix.starting(2);
+
+        assert_eq!(prefix.text(), "[ 1] web   :  2");
+    }
+
+    #[test]
+    fn escapes_braces() {
+        assert_eq!(prefix("{{{name}}}").text(), "{web   }");
+        assert_eq!(prefix("{{name}}").text(), "{name}");
+    }
+
+    #[test]
+    fn keeps_width_as_the_run_changes() {
+        let prefix = prefix("{name} {pid} {elapsed}");
+        assert_eq!(prefix.text(), "web          -      -");
+
+        prefix.spawned(Some(42));
+        let text = prefix.text();
+        assert!(text.starts_with("web         42 "));
+        assert_eq!(text.len(), 21);
+        prefix.run.lock().unwrap().started = Some(Instant::now() - Duration::from_secs(75));
+        assert_eq!(prefix.text(), "web         42  1m15s");
+    }
+
+    #[test]
+    fn formats_time() {
+        let time = prefix("{time}").text();
+        assert_eq!(time.len(), 8);
+        assert_eq!(time.matches(':').count(), 2);
+
+        let year = prefix("{time:%Y}").text();
+        assert_eq!(year, Local::now().format("%Y").to_string());
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert_eq!(error("{time:%Q}"), "invalid time format in {time:%Q}");
+        assert!(error("{nme}").starts_with("unknown placeholder {nme}, expected name"));
+        assert!(error("{name:x}").starts_with("unknown placeholder {name:x}"));
+        assert_eq!(error("{name"), "unclosed { in prefix \"{name\"");
+        assert_eq!(
+            error("name}"),
+            "unmatched } in prefix \"name}\", use }} for a literal }"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{config::Profile, discover::ConfigFile, layer};
+
+/// Profile applied when no tasks or profile are named.
+pub const DEFAULT_PROFILE: &str = "default";
+
+pub fn exists(table: &toml::Table, name: &str) -> bool {
+    table
+        .get("profiles")
+        .and_then(|profiles| profiles.get(name))
+        .is_some()
+}
+
+/// Applies a profile's overrides to the raw config, returning the tasks it selects.
+pub fn apply(config_file: &mut ConfigFile, name: &str) -> anyhow::Result<Vec<String>> {
+    let profile = config_file
+        .table
+        .get("profiles")
+        .and_then(|profiles| profiles.get(name))
+        .cloned()
+        .with_context(|| format!("unknown profile {name}"))?;
+    let profile =
+        Profile::deserialize(profile).with_context(|| format!("invalid profile {name}"))?;
+
+    let Some(toml::Value::Table(tasks)) = config_file.table.get_mut("tasks") else {
+        bail!("profile {name} has no tasks to run");
+    };
+    for task in profile.tasks.iter().chain(profile.overrides.keys()) {
+        if !tasks.contains_key(task) {
+            bail!("profile {name} refers to unknown task {task}");
+        }
+    }
+
+    for (task_name, task) in tasks.iter_mut() {
+        let toml::Value::Table(task) = task else {
+            continue;
+        };
+        let prefix = format!("tasks.{task_name}");
+        let origin = format!("profile {name}");
+        let sources = &mut config_file.sources;
+        layer::merge(task, profile.options.clone(), &prefix, &origin, sources);
+        if let Some(overrides) = profile.overrides.get(task_name) {
+            layer::merge(task, overrides.clone(), &prefix, &origin, sources);
+        }
+    }
+
+    Ok(profile.tasks)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::layer::Sources;
+
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [tasks.web]
+        command = "serve"
+        env = { PORT = "80", HOST = "localhost" }
+
+        [tasks.worker]
+        command = "work"
+
+        [profiles.dev]
+        tasks = ["web"]
+        restart = true
+        overrides.web.env = { PORT = "8080" }
+        overrides.worker.command = "work --verbose"
+
+        [profiles.broken]
+        overrides.db.command = "postgres"
+    "#;
+
+    fn config_file() -> ConfigFile {
+        ConfigFile {
+            path: PathBuf::from("tasks.toml"),
+            table: toml::from_str(CONFIG).unwrap(),
+            sources: Sources::default(),
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn applies_overrides() {
+        let mut config_file = config_file();
+        let tasks = apply(&mut config_file, "dev").unwrap();
+        assert_eq!(tasks, ["web"]);
+
+        let cases = [
+            ("tasks.web.command", Some("\"serve\""), None),
+            ("tasks.web.restart", Some("true"), Some("profile dev")),
+            ("tasks.web.env.PORT", Some("\"8080\""), Some("profile dev")),
+            ("tasks.web.env.HOST", Some("\"localhost\""), None),
+            ("tasks.worker.restart", Some("true"), Some("profile dev")),
+            (
+                "tasks.worker.command",
+                Some("\"work --verbose\""),
+                Some("profile dev"),
+            ),
+        ];
+        let leaves = layer::leaves(&config_file.table);
+        for (key, value, source) in cases {
+            let found = leaves
+                .iter()
+                .find(|(leaf, _)| leaf == key)
+                .map(|(_, value)| value.to_string());
+            assert_eq!(found.as_deref(), value, "{key}");
+            assert_eq!(config_file.sources.get(key), source, "{key}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_profiles_and_tasks() {
+        let cases = [
+            ("test", "unknown profile test"),
+            ("broken", "profile broken refers to unknown task db"),
+        ];
+        for (name, message) in cases {
+            let err = apply(&mut config_file(), name).unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+    }
+}
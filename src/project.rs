@@ -0,0 +1,143 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use tokio::process::Command;
+
+/// Paths shared by every task, so they behave the same regardless of where concurrently was run from.
+pub struct Project {
+    /// Directory containing the config, which relative paths are resolved from.
+    pub root: PathBuf,
+    /// Cargo's target directory, only looked up when there are cargo tasks.
+    pub target_dir: Option<PathBuf>,
+    /// Packages in the cargo workspace, only looked up when there are cargo tasks.
+    packages: Vec<Package>,
+}
+
+#[derive(Deserialize)]
+struct CargoMetadata {
+    target_directory: PathBuf,
+    packages: Vec<Package>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    manifest_path: PathBuf,
+    dependencies: Vec<Dependency>,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Dependency {
+    kind: Option<String>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    kind: Vec<String>,
+    src_path: PathBuf,
+}
+
+impl Project {
+    pub async fn new(root: PathBuf, has_cargo_tasks: bool) -> anyhow::Result<Self> {
+        let (target_dir, packages) = if has_cargo_tasks {
+            let metadata = cargo_metadata(&root).await?;
+            (Some(metadata.target_directory), metadata.packages)
+        } else {
+            (None, Vec::new())
+        };
+
+        Ok(Project {
+            root,
+            target_dir,
+            packages,
+        })
+    }
+
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Globs of the sources of a workspace package and its path dependencies, which need a rebuild
+    /// when they change. Tests, examples and benches are left out.
+    pub fn cargo_sources(&self, package: &str) -> Vec<String> {
+        let mut sources = Vec::new();
+        let mut seen = HashSet::new();
+        let mut dirs: Vec<_> = self
+            .packages
+            .iter()
+            .filter(|p| p.name == package)
+            .filter_map(|p| p.manifest_path.parent())
+            .map(Path::to_path_buf)
+            .collect();
+
+        while let Some(dir) = dirs.pop() {
+            if !seen.insert(dir.clone()) {
+                continue;
+            }
+            let package = self
+                .packages
+                .iter()
+                .find(|p| p.manifest_path.parent() == Some(dir.as_path()));
+            let Some(package) = package else {
+                // Path dependencies outside the workspace aren't in the metadata, so their sources
+                // are assumed to be in the usual places.
+                for source in ["Cargo.toml", "build.rs", "src/**"] {
+                    sources.push(dir.join(source).to_string_lossy().into_owned());
+                }
+                continue;
+            };
+
+            sources.push(package.manifest_path.to_string_lossy().into_owned());
+            for target in &package.targets {
+                if target
+                    .kind
+                    .iter()
+                    .any(|kind| matches!(kind.as_str(), "test" | "example" | "bench"))
+                {
+                    continue;
+                }
+                let source = if target.kind.iter().any(|kind| kind == "custom-build") {
+                    target.src_path.clone()
+                } else {
+                    let dir = target.src_path.parent().unwrap_or(&dir);
+                    dir.join("**")
+                };
+                sources.push(source.to_string_lossy().into_owned());
+            }
+            dirs.extend(
+                package
+                    .dependencies
+                    .iter()
+                    .filter(|dependency| dependency.kind.as_deref() != Some("dev"))
+                    .filter_map(|dependency| dependency.path.clone()),
+            );
+        }
+
+        sources.sort();
+        sources.dedup();
+        sources
+    }
+}
+
+async fn cargo_metadata(root: &Path) -> anyhow::Result<CargoMetadata> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(root)
+        .output()
+        .await
+        .context("failed to run cargo metadata")?;
+    if !output.status.success() {
+        bail!(
+            "failed to run cargo metadata: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    serde_json::from_slice(&output.stdout).context("failed to parse cargo metadata")
+}
@@ -0,0 +1,80 @@
+use std::{collections::BTreeMap, future, path::Path, process::Stdio};
+
+use tokio::{
+    fs,
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    process::Command,
+    time,
+};
+
+use crate::config::{Probe, ReadyOptions};
+
+/// Polls the probe until it passes, returning `false` if it didn't pass before the timeout.
+///
+/// Output probes never pass here, since they are matched against the task's output as it is read.
+/// File and command probes are relative to the task's working directory.
+pub async fn wait(opts: &ReadyOptions, cwd: &Path, env: &BTreeMap<String, String>) -> bool {
+    let poll = async {
+        loop {
+            if check(&opts.probe, cwd, env).await {
+                return;
+            }
+            time::sleep(opts.interval).await;
+        }
+    };
+
+    time::timeout(opts.timeout, poll).await.is_ok()
+}
+
+async fn check(probe: &Probe, cwd: &Path, env: &BTreeMap<String, String>) -> bool {
+    match probe {
+        Probe::Output(_) => future::pending().await,
+        Probe::Tcp(address) => TcpStream::connect(address).await.is_ok(),
+        Probe::Http(url) => http_ok(url).await.unwrap_or(false),
+        Probe::File(path) => fs::try_exists(cwd.join(path)).await.unwrap_or(false),
+        Probe::Command(command) => Command::new("sh")
+            .arg("-c")
+            .arg(command.to_string())
+            .current_dir(cwd)
+            .env_clear()
+            .envs(env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status()
+            .await
+            .map(|status| status.success())
+            .unwrap_or(false),
+    }
+}
+
+async fn http_ok(url: &str) -> io::Result<bool> {
+    let rest = url.strip_prefix("http://").unwrap_or(url);
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+
+    let mut stream = TcpStream::connect(address).await?;
+    stream
+        .write_all(
+            format!("GET {path} HTTP/1.0\r\nHost: {authority}\r\nConnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .await?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line).await?;
+
+    Ok(status_line
+        .split_whitespace()
+        .nth(1)
+        .is_some_and(|code| code.starts_with('2')))
+}
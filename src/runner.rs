@@ -0,0 +1,1166 @@
+use std::{
+    future::Future,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
+
+use colored::Colorize;
+use futures::future;
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use regex::Regex;
+use tokio::{
+    io, signal,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::{AbortHandle, JoinHandle},
+    time,
+};
+
+use crate::{
+    commands,
+    config::{ExitCode, ExitOptions, Probe, RestartPolicy, Stdin, Success},
+    events,
+    keyboard::{self, Keyboard},
+    log::{error, info, warn},
+    output::{self, Phase},
+    ready,
+    socket::{self, TaskInfo},
+    summary::{self, Outcome, TaskStatus, TaskSummary},
+    task::Task,
+    tui::Tui,
+    watch::Watcher,
+};
+
+/// Switches keyboard input to the next task.
+const FOCUS_KEY: u8 = 0x14; // Ctrl-T
+
+/// Interrupts concurrently when the focused task isn't running to receive it.
+const INTERRUPT_KEY: u8 = 0x03; // Ctrl-C
+
+/// How long a run has to stay up for the task's backoff to start over.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(10);
+
+/// Handed to a running task so it can report back to the runner.
+#[derive(Clone)]
+pub struct Notifier {
+    index: usize,
+    generation: usize,
+    ready_pattern: Option<Regex>,
+    tx: UnboundedSender<Event>,
+}
+
+impl Notifier {
+    /// Reports the task's process, along with where to send keyboard input if it takes any.
+    pub fn spawned(&self, pid: Option<u32>, input: Option<UnboundedSender<Vec<u8>>>) {
+        self.send(EventKind::Spawned(pid, input));
+    }
+
+    /// Checks a line of the task's output against its output readiness probe.
+    pub fn line(&self, line: &str) {
+        if self
+            .ready_pattern
+            .as_ref()
+            .is_some_and(|pattern| pattern.is_match(line))
+        {
+            self.send(EventKind::Ready);
+        }
+    }
+
+    fn exited(&self, result: io::Result<ExitStatus>) {
+        self.send(EventKind::Exited(result));
+    }
+
+    fn send(&self, kind: EventKind) {
+        let _ = self.tx.send(Event {
+            index: self.index,
+            generation: self.generation,
+            kind,
+        });
+    }
+}
+
+struct Event {
+    index: usize,
+    generation: usize,
+    kind: EventKind,
+}
+
+enum EventKind {
+    Spawned(Option<u32>, Option<UnboundedSender<Vec<u8>>>),
+    Ready,
+    NotReady,
+    Exited(io::Result<ExitStatus>),
+    /// A watched file changed.
+    Changed(PathBuf),
+    /// The prepare step run after a change finished, successfully or not.
+    Prepared(bool),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// Waiting for dependencies to start.
+    Pending,
+    /// Spawned, or sleeping before being spawned.
+    Running,
+    /// Exited and waiting to be restarted.
+    Restarting,
+    /// Exited for good by itself.
+    Exited,
+    /// Stopped by concurrently.
+    Stopped,
+    /// Never started since one of its dependencies could not be started.
+    Skipped,
+}
+
+struct TaskState {
+    status: Status,
+    /// Whether the current run has passed its readiness probe.
+    ready: bool,
+    /// Whether any run has passed its readiness probe, allowing dependents to start.
+    been_ready: bool,
+    /// Restarts after failures, limited by the task's `retries`.
+    retries: usize,
+    /// All restarts, including reloads.
+    restarts: usize,
+    /// Restarts since the task last reloaded or stayed up for [`HEALTHY_UPTIME`], which the
+    /// backoff grows with.
+    failures: usize,
+    generation: usize,
+    /// Process of the current run once it has been spawned, which leads its process group.
+    pid: Option<u32>,
+    /// How the last run exited, if it exited at all.
+    exit_status: Option<ExitStatus>,
+    /// When the current run was spawned.
+    started: Option<Instant>,
+    /// Time spent running, across all runs.
+    uptime: Duration,
+    handle: Option<JoinHandle<()>>,
+    probe: Option<JoinHandle<()>>,
+    /// Prepare step run after the latest change to the task's watched files.
+    preparing: Option<JoinHandle<()>>,
+    /// Changes to the task's watched files, which `Prepared` events are matched against.
+    changes: usize,
+    /// Keyboard input for the current run, if the task takes it.
+    input: Option<UnboundedSender<Vec<u8>>>,
+}
+
+/// Runs tasks in dependency order, restarting them as configured.
+///
+/// Tasks are expected to be in topological order.
+pub struct Runner {
+    tasks: Vec<Task>,
+    dependencies: Vec<Vec<usize>>,
+    states: Vec<TaskState>,
+    exit: ExitOptions,
+    /// How long preparing each task took, if it had anything to prepare.
+    prepared: Vec<Option<Duration>>,
+    /// Tasks which have exited for good by themselves, in the order they exited.
+    finished: Vec<usize>,
+    /// Whether concurrently was stopped by Ctrl-C, rather than quit or left to finish.
+    interrupted: bool,
+    /// Task receiving keyboard input.
+    focus: Option<usize>,
+    /// Tasks stopped by themselves through the TUI, along with the runs they're stopping.
+    stopping: Vec<(JoinHandle<()>, Option<AbortHandle>)>,
+    tui: bool,
+    /// Where to listen for requests from `concurrently ctl`.
+    socket: Option<PathBuf>,
+    /// Last status reported for each task.
+    phases: Vec<Option<Phase>>,
+    tx: UnboundedSender<Event>,
+    rx: UnboundedReceiver<Event>,
+    control_tx: UnboundedSender<Control>,
+    control_rx: Option<UnboundedReceiver<Control>>,
+}
+
+/// Commands for the runner from the user, through the TUI or typed on stdin.
+pub enum Control {
+    /// Restart a task, or start it again if it has stopped.
+    Restart(usize),
+    /// Stop a task with its stop signal.
+    Stop(usize),
+    /// Start a task again once it has exited or been stopped.
+    Start(usize),
+    /// List the status of every task.
+    List,
+    /// Reply with the status of every task.
+    Status(oneshot::Sender<Vec<TaskInfo>>),
+    /// Send a signal to a task's process group.
+    Signal(usize, Signal),
+    /// Send keyboard input to a task.
+    Input(usize, Vec<u8>),
+    /// Stop all tasks and exit, or kill them if they're already stopping.
+    Quit,
+}
+
+impl Runner {
+    pub fn new(tasks: Vec<Task>, exit: ExitOptions, prepared: Vec<Option<Duration>>) -> Self {
+        let dependencies = tasks
+            .iter()
+            .map(|task| {
+                task.depends_on
+                    .iter()
+                    .filter_map(|dependency| tasks.iter().position(|t| t.name == *dependency))
+                    .collect()
+            })
+            .collect();
+        let states = tasks
+            .iter()
+            .map(|_| TaskState {
+                status: Status::Pending,
+                ready: false,
+                been_ready: false,
+                retries: 0,
+                restarts: 0,
+                failures: 0,
+                generation: 0,
+                pid: None,
+                exit_status: None,
+                started: None,
+                uptime: Duration::ZERO,
+                handle: None,
+                probe: None,
+                preparing: None,
+                changes: 0,
+                input: None,
+            })
+            .collect();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+
+        Runner {
+            phases: vec![None; tasks.len()],
+            tasks,
+            dependencies,
+            states,
+            exit,
+            prepared,
+            finished: Vec::new(),
+            interrupted: false,
+            focus: None,
+            stopping: Vec::new(),
+            tui: false,
+            socket: None,
+            tx,
+            rx,
+            control_tx,
+            control_rx: Some(control_rx),
+        }
+    }
+
+    /// Listens for requests from `concurrently ctl` on a socket at `path` while running.
+    pub fn with_socket(mut self, path: PathBuf) -> Self {
+        self.socket = Some(path);
+        self
+    }
+
+    /// Shows the tasks in a full screen TUI rather than printing their output.
+    pub fn with_tui(mut self) -> Self {
+        self.tui = true;
+        self
+    }
+
+    /// Runs the tasks until they have all exited or the run is stopped, then prints a summary and
+    /// returns the exit code.
+    pub async fn run(mut self) -> i32 {
+        let mut controls = self.control_rx.take().expect("runner is only run once");
+        let tui = match self.tui {
+            true => match Tui::start(&self.tasks, self.control_tx.clone()) {
+                Ok(tui) => Some(tui),
+                Err(err) => {
+                    warn(format!("could not start the TUI: {err:#}"));
+                    None
+                }
+            },
+            false => None,
+        };
+        let names: Vec<_> = self.tasks.iter().map(|task| task.name.clone()).collect();
+        let _server = self.socket.take().and_then(|path| {
+            socket::Server::start(path, names.clone(), self.control_tx.clone())
+                .map_err(|err| warn(format!("control socket is unavailable: {err:#}")))
+                .ok()
+        });
+        let _watchers = self.watch();
+        let interactive = tui.is_none() && keyboard::in_foreground();
+        let mut keyboard = (interactive && self.focus_next()).then(Keyboard::new);
+        if interactive && keyboard.is_none() {
+            commands::read(names, self.control_tx.clone());
+        }
+        self.start_pending();
+        self.report_phases();
+
+        // The TUI stays open once every task has exited, until it's quit.
+        let mut quit = false;
+        while self.is_active() || tui.is_some() {
+            tokio::select! {
+                Some(event) = self.rx.recv() => {
+                    if !self.handle_event(event) {
+                        break;
+                    }
+                }
+                Some(control) = controls.recv() => {
+                    if !self.control(control) {
+                        quit = true;
+                        break;
+                    }
+                }
+                keys = next_keys(&mut keyboard) => {
+                    if !self.keys(&keys) {
+                        output::message("Shutting down...");
+                        self.interrupted = true;
+                        break;
+                    }
+                }
+                _ = signal::ctrl_c() => {
+                    output::message("Shutting down...");
+                    self.interrupted = true;
+                    break;
+                }
+            }
+            self.report_phases();
+        }
+
+        drop(keyboard);
+        self.shutdown(&mut controls).await;
+        if let Some(tui) = tui {
+            self.report_phases();
+            if !quit {
+                output::message("All tasks have stopped, press q to quit");
+                while let Some(control) = controls.recv().await {
+                    if matches!(control, Control::Quit) {
+                        break;
+                    }
+                }
+            }
+            tui.close().await;
+        }
+        self.print_summary();
+        self.exit_code()
+    }
+
+    /// Returns `false` when the runner should shut down.
+    fn handle_event(&mut self, event: Event) -> bool {
+        let index = event.index;
+        let generation = match event.kind {
+            EventKind::Changed(_) => event.generation,
+            EventKind::Prepared(_) => self.states[index].changes,
+            _ => self.states[index].generation,
+        };
+        if event.generation != generation {
+            return true;
+        }
+
+        match event.kind {
+            EventKind::Spawned(pid, input) => {
+                self.states[index].pid = pid;
+                self.states[index].input = input;
+                self.states[index].started = Some(Instant::now());
+                self.spawned(index);
+            }
+            EventKind::Ready => {
+                if !self.states[index].ready {
+                    let task = &self.tasks[index];
+                    task.print("ready".bold().white());
+                    events::emit(events::Event::Ready { task: &task.name });
+                    self.set_ready(index);
+                }
+            }
+            EventKind::NotReady => {
+                let state = &mut self.states[index];
+                if state.ready {
+                    return true;
+                }
+                if let Some(handle) = state.handle.take() {
+                    handle.abort();
+                }
+                self.stop_uptime(index);
+
+                let timeout = self.tasks[index]
+                    .ready
+                    .as_ref()
+                    .map(|opts| opts.timeout)
+                    .unwrap_or_default();
+                let reason = format!("did not become ready within {:.2}s", timeout.as_secs_f32());
+                if !self.retry(index, &reason) {
+                    return self.finish(index, None);
+                }
+            }
+            // Tasks stopped through the TUI stay stopped.
+            EventKind::Exited(result) if self.states[index].status == Status::Stopped => {
+                self.stop_uptime(index);
+                let state = &mut self.states[index];
+                state.exit_status = result.ok();
+                state.input = None;
+                state.handle = None;
+                state.pid = None;
+            }
+            EventKind::Exited(Ok(status)) => {
+                self.stop_probe(index);
+                self.stop_uptime(index);
+                self.states[index].input = None;
+                self.states[index].handle = None;
+                self.states[index].pid = None;
+                return self.exited(index, status);
+            }
+            EventKind::Exited(Err(err)) => {
+                self.stop_probe(index);
+                self.stop_uptime(index);
+                self.states[index].input = None;
+                let state = &mut self.states[index];
+                state.handle = None;
+                state.pid = None;
+                error(format!(
+                    "task {} could not be started: {err}",
+                    self.tasks[index].name
+                ));
+                return self.finish(index, None);
+            }
+            EventKind::Changed(path) => self.changed(index, &path),
+            EventKind::Prepared(success) => self.prepared(index, success),
+        }
+
+        true
+    }
+
+    /// Returns `false` when the user asked to quit.
+    fn control(&mut self, control: Control) -> bool {
+        match control {
+            Control::Restart(index) => match self.states[index].status {
+                Status::Pending | Status::Skipped => self.tasks[index].print(
+                    "can't restart before its dependencies are ready"
+                        .bold()
+                        .red(),
+                ),
+                _ => {
+                    self.tasks[index].print("restarting".bold().white());
+                    self.reload(index);
+                }
+            },
+            Control::Stop(index) => self.stop_task(index),
+            Control::Start(index) => match self.states[index].status {
+                Status::Pending | Status::Skipped => self.tasks[index]
+                    .print("can't start before its dependencies are ready".bold().red()),
+                Status::Running | Status::Restarting => {
+                    self.tasks[index].print("is already running".bold().white())
+                }
+                Status::Exited | Status::Stopped => {
+                    self.tasks[index].print("starting again".bold().white());
+                    self.reload(index);
+                }
+            },
+            Control::List => summary::print_status(&self.statuses()),
+            Control::Status(reply) => {
+                let _ = reply.send(self.statuses().iter().map(TaskInfo::from).collect());
+            }
+            Control::Signal(index, signal) => {
+                let task = &self.tasks[index];
+                match self.states[index].pid {
+                    Some(pid) => {
+                        task.print(format!("sending {}", signal.as_str()).bold().white());
+                        let _ = killpg(Pid::from_raw(pid as i32), signal);
+                    }
+                    None => task.print(
+                        format!("isn't running, not sending {}", signal.as_str())
+                            .bold()
+                            .red(),
+                    ),
+                }
+            }
+            Control::Input(index, bytes) => {
+                if let Some(input) = &self.states[index].input {
+                    let _ = input.send(bytes);
+                }
+            }
+            Control::Quit => {
+                if self.is_active() {
+                    output::message("Shutting down...");
+                }
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Stops a single task, which stays stopped until it's restarted.
+    fn stop_task(&mut self, index: usize) {
+        if !matches!(
+            self.states[index].status,
+            Status::Running | Status::Restarting
+        ) {
+            return;
+        }
+
+        self.stop_probe(index);
+        let task = self.tasks[index].clone();
+        let state = &mut self.states[index];
+        state.status = Status::Stopped;
+        state.exit_status = None;
+        if let Some(preparing) = state.preparing.take() {
+            preparing.abort();
+        }
+        let pid = state.pid.take();
+        let handle = state.handle.take();
+        let run = handle.as_ref().map(JoinHandle::abort_handle);
+        let stopping = tokio::spawn(async move { stop(&task, pid, handle).await });
+        self.stopping
+            .retain(|(stopping, _)| !stopping.is_finished());
+        self.stopping.push((stopping, run));
+    }
+
+    /// Reports the status of each task which changed since it was last reported.
+    fn report_phases(&mut self) {
+        for index in 0..self.tasks.len() {
+            let phase = self.phase(index);
+            if self.phases[index] != Some(phase) {
+                self.phases[index] = Some(phase);
+                output::phase(&self.tasks[index].name, phase);
+            }
+        }
+    }
+
+    fn phase(&self, index: usize) -> Phase {
+        let state = &self.states[index];
+        match state.status {
+            Status::Pending => Phase::Pending,
+            Status::Running if state.ready => Phase::Running,
+            Status::Running => Phase::Starting,
+            Status::Restarting => Phase::Restarting,
+            Status::Exited if state.exit_status.is_some_and(|s| s.success()) => Phase::Exited,
+            Status::Exited => Phase::Failed,
+            Status::Stopped => Phase::Stopped,
+            Status::Skipped => Phase::Skipped,
+        }
+    }
+
+    fn statuses(&self) -> Vec<TaskStatus<'_>> {
+        self.tasks
+            .iter()
+            .zip(&self.states)
+            .enumerate()
+            .map(|(index, (task, state))| TaskStatus {
+                name: &task.name,
+                phase: self.phase(index),
+                pid: state.pid,
+                restarts: state.restarts,
+                uptime: state.uptime + state.started.map_or(Duration::ZERO, |s| s.elapsed()),
+            })
+            .collect()
+    }
+
+    /// Forwards keyboard input to the focused task, moving focus to the next task on Ctrl-T.
+    ///
+    /// Returns `false` when Ctrl-C is pressed while the focused task isn't running, which
+    /// interrupts concurrently instead.
+    fn keys(&mut self, keys: &[u8]) -> bool {
+        for (i, keys) in keys.split(|key| *key == FOCUS_KEY).enumerate() {
+            if i > 0 {
+                self.focus_next();
+            }
+            let input = self
+                .focus
+                .and_then(|index| self.states[index].input.as_ref());
+            match input {
+                Some(input) if !keys.is_empty() => {
+                    let _ = input.send(keys.to_vec());
+                }
+                None if keys.contains(&INTERRUPT_KEY) => return false,
+                _ => {}
+            }
+        }
+        true
+    }
+
+    /// Moves focus to the next task which takes keyboard input, returning whether there is one.
+    fn focus_next(&mut self) -> bool {
+        let start = self.focus.map_or(0, |index| index + 1);
+        let count = self.tasks.len();
+        self.focus = (start..start + count)
+            .map(|index| index % count)
+            .find(|&index| self.tasks[index].stdin == Stdin::Inherit);
+
+        match self.focus {
+            Some(index) => {
+                info(format!(
+                    "keyboard input goes to task {}, press Ctrl-T to switch",
+                    self.tasks[index].name
+                ));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Starts watching the files of each task with `watch` patterns.
+    fn watch(&self) -> Vec<Watcher> {
+        (0..self.tasks.len())
+            .filter(|&index| !self.tasks[index].watch.is_empty())
+            .filter_map(|index| {
+                let tx = self.tx.clone();
+                let watcher = Watcher::new(&self.tasks[index], move |path| {
+                    let _ = tx.send(Event {
+                        index,
+                        generation: 0,
+                        kind: EventKind::Changed(path),
+                    });
+                });
+                match watcher {
+                    Ok(watcher) => Some(watcher),
+                    Err(err) => {
+                        let name = &self.tasks[index].name;
+                        warn(format!("could not watch files for task {name}: {err:#}"));
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Re-runs the task's prepare step after its watched files changed, restarting the task once
+    /// it succeeds.
+    fn changed(&mut self, index: usize, path: &Path) {
+        let task = self.tasks[index].clone();
+        let state = &mut self.states[index];
+        if matches!(state.status, Status::Pending | Status::Skipped) {
+            return;
+        }
+        // Only `start` or `rs` bring back a task stopped on purpose under `unless-stopped`.
+        if state.status == Status::Stopped && task.restart == RestartPolicy::UnlessStopped {
+            return;
+        }
+
+        let path = path.strip_prefix(&task.cwd).unwrap_or(path);
+        task.print(format!("{} changed", path.display()).bold().white());
+        state.changes += 1;
+        let generation = state.changes;
+        let tx = self.tx.clone();
+        let preparing = tokio::spawn(async move {
+            let success = match task.prepare(None).await {
+                Some(result) => result.is_ok_and(|status| status.success()),
+                None => true,
+            };
+            let _ = tx.send(Event {
+                index,
+                generation,
+                kind: EventKind::Prepared(success),
+            });
+        });
+        if let Some(old) = state.preparing.replace(preparing) {
+            old.abort();
+        }
+    }
+
+    fn prepared(&mut self, index: usize, success: bool) {
+        let state = &mut self.states[index];
+        state.preparing = None;
+        if matches!(state.status, Status::Pending | Status::Skipped) {
+            return;
+        }
+        if !success {
+            let outcome = if state.handle.is_some() {
+                "prepare failed, keeping the current run"
+            } else {
+                "prepare failed, not restarting"
+            };
+            self.tasks[index].print(outcome.bold().red());
+            return;
+        }
+
+        self.reload(index);
+    }
+
+    /// Restarts a task after a change, stopping the current run before starting the next.
+    fn reload(&mut self, index: usize) {
+        self.stop_probe(index);
+        self.stop_uptime(index);
+        self.finished.retain(|finished| *finished != index);
+
+        let task = self.tasks[index].clone();
+        let state = &mut self.states[index];
+        state.retries = 0;
+        state.restarts += 1;
+        state.failures = 0;
+        state.exit_status = None;
+        let pid = state.pid.take();
+        let handle = state.handle.take();
+        self.spawn_after(index, None, async move { stop(&task, pid, handle).await });
+    }
+
+    /// Starts the task's readiness probe, or marks it ready straight away if it has none.
+    fn spawned(&mut self, index: usize) {
+        match self.tasks[index].ready.clone() {
+            Some(opts) => {
+                let task = &self.tasks[index];
+                task.print("starting".bold().white());
+                let cwd = task.cwd.clone();
+                let env = task.env.clone();
+                let notifier = self.notifier(index);
+                self.states[index].probe = Some(tokio::spawn(async move {
+                    if ready::wait(&opts, &cwd, &env).await {
+                        notifier.send(EventKind::Ready);
+                    } else {
+                        notifier.send(EventKind::NotReady);
+                    }
+                }));
+            }
+            None => self.set_ready(index),
+        }
+    }
+
+    fn set_ready(&mut self, index: usize) {
+        self.stop_probe(index);
+        let state = &mut self.states[index];
+        state.ready = true;
+        state.been_ready = true;
+        self.start_pending();
+    }
+
+    fn stop_uptime(&mut self, index: usize) {
+        let state = &mut self.states[index];
+        if let Some(started) = state.started.take() {
+            state.uptime += started.elapsed();
+            if started.elapsed() >= HEALTHY_UPTIME {
+                state.failures = 0;
+            }
+        }
+    }
+
+    fn stop_probe(&mut self, index: usize) {
+        let state = &mut self.states[index];
+        state.ready = false;
+        if let Some(probe) = state.probe.take() {
+            probe.abort();
+        }
+    }
+
+    /// Applies the task's restart policy once it has exited, returning `false` if the runner
+    /// should shut down.
+    fn exited(&mut self, index: usize, status: ExitStatus) -> bool {
+        let restart = self.tasks[index].restart;
+        let reason = match (status.code(), status.signal()) {
+            (Some(0), _) => "exited successfully".to_string(),
+            (Some(code), _) => format!("exited with status code {code}"),
+            (None, Some(signal)) => format!("was killed by signal {signal}"),
+            (None, None) => "exited".to_string(),
+        };
+
+        self.states[index].exit_status = Some(status);
+        if status.success() {
+            if matches!(
+                restart,
+                RestartPolicy::Always | RestartPolicy::UnlessStopped
+            ) {
+                self.restart(index, &reason);
+                return true;
+            }
+            return self.finish(index, Some(status));
+        }
+        self.retry(index, &reason) || self.finish(index, Some(status))
+    }
+
+    /// Restarts a failed task, returning `false` if its restart policy or retries don't allow it.
+    fn retry(&mut self, index: usize, reason: &str) -> bool {
+        let task = &self.tasks[index];
+        let state = &mut self.states[index];
+        match task.restart {
+            RestartPolicy::No => {
+                error(format!("task {} {reason}", task.name));
+                return false;
+            }
+            RestartPolicy::OnFailure if state.retries >= task.max_retries => {
+                error(format!("task {} {reason} too many times", task.name));
+                return false;
+            }
+            RestartPolicy::OnFailure => state.retries += 1,
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => {}
+        }
+
+        self.restart(index, reason);
+        true
+    }
+
+    /// Records that a task has exited for good by itself, returning `false` if the other tasks
+    /// should be stopped.
+    fn finish(&mut self, index: usize, exit_status: Option<ExitStatus>) -> bool {
+        let state = &mut self.states[index];
+        state.status = Status::Exited;
+        state.exit_status = exit_status;
+        self.finished.push(index);
+        self.start_pending();
+
+        let failed = !exit_status.is_some_and(|status| status.success());
+        if self.exit.kill_others || failed && self.exit.kill_others_on_fail {
+            let task = &self.tasks[index];
+            let outcome = if failed { "failed" } else { "exited" };
+            info(format!(
+                "task {} {outcome}, stopping other tasks",
+                task.name
+            ));
+            return false;
+        }
+
+        true
+    }
+
+    fn succeeded(&self) -> bool {
+        let succeeded = |index: usize| {
+            let state = &self.states[index];
+            state.status == Status::Exited
+                && state.exit_status.is_some_and(|status| status.success())
+        };
+
+        !self.interrupted
+            && match &self.exit.success {
+                Success::All => (0..self.tasks.len())
+                    .all(|index| self.states[index].status == Status::Stopped || succeeded(index)),
+                Success::First => self.finished.first().is_some_and(|index| succeeded(*index)),
+                Success::Last => self.finished.last().is_some_and(|index| succeeded(*index)),
+                Success::Task(name) => self
+                    .tasks
+                    .iter()
+                    .position(|task| task.name == *name)
+                    .is_some_and(succeeded),
+            }
+    }
+
+    /// The run's exit code: 0 if it succeeded, 130 if it was interrupted, and otherwise the code
+    /// of a failed task chosen by `exit_code`, where being killed by a signal counts as 128 plus
+    /// the signal number.
+    fn exit_code(&self) -> i32 {
+        if self.interrupted {
+            return 130;
+        }
+        if self.succeeded() {
+            return 0;
+        }
+
+        let failed = |index: &usize| {
+            let state = &self.states[*index];
+            state.status == Status::Exited && !state.exit_status.is_some_and(|s| s.success())
+        };
+        let failures: Vec<usize> = match &self.exit.success {
+            Success::All => self.finished.iter().copied().filter(failed).collect(),
+            Success::First => self.finished.first().copied().into_iter().collect(),
+            Success::Last => self.finished.last().copied().into_iter().collect(),
+            Success::Task(name) => self
+                .tasks
+                .iter()
+                .position(|task| task.name == *name)
+                .filter(failed)
+                .into_iter()
+                .collect(),
+        };
+        let mut codes = failures
+            .into_iter()
+            .map(|index| match self.states[index].exit_status {
+                Some(status) => status
+                    .code()
+                    .or(status.signal().map(|signal| 128 + signal))
+                    .unwrap_or(1),
+                None => 1,
+            });
+
+        match self.exit.exit_code {
+            ExitCode::First => codes.next(),
+            ExitCode::Last => codes.next_back(),
+            ExitCode::Max => codes.max(),
+        }
+        .filter(|code| *code != 0)
+        .unwrap_or(1)
+    }
+
+    fn print_summary(&self) {
+        let summaries: Vec<_> = self
+            .tasks
+            .iter()
+            .zip(&self.states)
+            .zip(&self.prepared)
+            .map(|((task, state), prepare)| TaskSummary {
+                name: &task.name,
+                outcome: match state.status {
+                    Status::Exited if state.exit_status.is_some_and(|s| s.success()) => {
+                        Outcome::Succeeded
+                    }
+                    Status::Exited => Outcome::Failed,
+                    Status::Skipped => Outcome::Skipped,
+                    _ => Outcome::Stopped,
+                },
+                exit_status: state.exit_status,
+                restarts: state.restarts,
+                uptime: state.uptime,
+                prepare: *prepare,
+            })
+            .collect();
+        if events::enabled() {
+            events::emit(events::Event::Shutdown {
+                code: self.exit_code(),
+                tasks: summaries.iter().map(Into::into).collect(),
+            });
+        } else {
+            summary::print(&summaries);
+        }
+    }
+
+    fn restart(&mut self, index: usize, reason: &str) {
+        let task = &self.tasks[index];
+        let state = &mut self.states[index];
+        let delay = task.backoff.delay(state.failures);
+        state.restarts += 1;
+        state.failures += 1;
+        events::emit(events::Event::Retry {
+            task: &task.name,
+            reason,
+            restart: state.restarts,
+            delay: delay.as_secs_f64(),
+        });
+        task.print(
+            format!(
+                "{reason}, restarting in {:.2}s (restart {})",
+                delay.as_secs_f32(),
+                state.restarts
+            )
+            .bold()
+            .yellow(),
+        );
+
+        self.spawn(index, Some(delay));
+        self.states[index].status = Status::Restarting;
+    }
+
+    /// Starts every pending task whose dependencies have all been ready.
+    fn start_pending(&mut self) {
+        for index in 0..self.tasks.len() {
+            if self.states[index].status != Status::Pending {
+                continue;
+            }
+
+            let dependencies = &self.dependencies[index];
+            let unstartable = dependencies.iter().find(|dependency| {
+                let state = &self.states[**dependency];
+                !state.been_ready
+                    && matches!(
+                        state.status,
+                        Status::Exited | Status::Stopped | Status::Skipped
+                    )
+            });
+            if let Some(dependency) = unstartable {
+                warn(format!(
+                    "skipping task {} since its dependency {} never became ready",
+                    self.tasks[index].name, self.tasks[*dependency].name
+                ));
+                self.states[index].status = Status::Skipped;
+                continue;
+            }
+
+            if dependencies
+                .iter()
+                .all(|dependency| self.states[*dependency].been_ready)
+            {
+                self.spawn(index, None);
+            }
+        }
+    }
+
+    fn spawn(&mut self, index: usize, delay: Option<Duration>) {
+        if let Some(old) = self.states[index].handle.take() {
+            old.abort();
+        }
+        self.spawn_after(index, delay, future::ready(()));
+    }
+
+    /// Spawns the task once `before` has finished.
+    fn spawn_after(
+        &mut self,
+        index: usize,
+        delay: Option<Duration>,
+        before: impl Future<Output = ()> + Send + 'static,
+    ) {
+        self.stop_probe(index);
+        let state = &mut self.states[index];
+        state.status = Status::Running;
+        state.generation += 1;
+        state.pid = None;
+        self.tasks[index].prefix.starting(state.restarts);
+
+        let task = self.tasks[index].clone();
+        let notifier = self.notifier(index);
+        let handle = tokio::spawn(async move {
+            before.await;
+            if let Some(delay) = delay {
+                time::sleep(delay).await;
+            }
+            let result = task.run(&notifier).await;
+            notifier.exited(result);
+        });
+
+        self.states[index].handle = Some(handle);
+    }
+
+    fn notifier(&self, index: usize) -> Notifier {
+        let ready_pattern = match self.tasks[index].ready.as_ref().map(|opts| &opts.probe) {
+            Some(Probe::Output(pattern)) => Some(pattern.clone()),
+            _ => None,
+        };
+
+        Notifier {
+            index,
+            generation: self.states[index].generation,
+            ready_pattern,
+            tx: self.tx.clone(),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.tasks
+            .iter()
+            .zip(&self.states)
+            .any(|(task, state)| match state.status {
+                // Tasks stopped by hand wait to be started again.
+                Status::Pending | Status::Running | Status::Restarting | Status::Stopped => true,
+                // Tasks watching files wait for them to change to run again.
+                Status::Exited => !task.watch.is_empty(),
+                Status::Skipped => false,
+            })
+    }
+
+    /// Stops tasks in reverse dependency order, so dependents go down before what they rely on.
+    ///
+    /// Each task is sent its stop signal and killed if it hasn't exited within its stop timeout.
+    /// Tasks which don't depend on each other are stopped at the same time, and a second Ctrl-C
+    /// or quit kills everything straight away.
+    async fn shutdown(&mut self, controls: &mut UnboundedReceiver<Control>) {
+        // Catch up on tasks spawned since the last event, so that they can be signalled.
+        while let Ok(event) = self.rx.try_recv() {
+            if let EventKind::Spawned(pid, _) = event.kind {
+                if event.generation == self.states[event.index].generation {
+                    self.states[event.index].pid = pid;
+                }
+            }
+        }
+
+        let mut stopping = std::mem::take(&mut self.stopping);
+        let abort_handles: Vec<AbortHandle> = self
+            .states
+            .iter()
+            .filter_map(|state| state.handle.as_ref().map(JoinHandle::abort_handle))
+            .chain(stopping.iter_mut().filter_map(|(_, run)| run.take()))
+            .collect();
+        let stop = async {
+            self.stop_all().await;
+            future::join_all(stopping.into_iter().map(|(stopping, _)| stopping)).await;
+        };
+        let force = async {
+            tokio::select! {
+                _ = signal::ctrl_c() => {}
+                _ = async {
+                    while let Some(control) = controls.recv().await {
+                        if matches!(control, Control::Quit) {
+                            break;
+                        }
+                    }
+                } => {}
+            }
+        };
+        tokio::select! {
+            _ = stop => {}
+            _ = force => {
+                output::message("Forcing shutdown...");
+                for handle in abort_handles {
+                    handle.abort();
+                }
+            }
+        }
+
+        // Record how the stopped tasks exited, for the summary.
+        while let Ok(event) = self.rx.try_recv() {
+            if let EventKind::Exited(Ok(status)) = event.kind {
+                let state = &mut self.states[event.index];
+                if event.generation == state.generation && state.status == Status::Stopped {
+                    state.exit_status = Some(status);
+                }
+            }
+        }
+        for index in 0..self.tasks.len() {
+            self.stop_uptime(index);
+        }
+    }
+
+    async fn stop_all(&mut self) {
+        // Tasks are sorted so that dependents come after their dependencies, so each task is
+        // stopped one stage after the last of its dependents.
+        let mut stages = vec![0; self.tasks.len()];
+        for index in (0..self.tasks.len()).rev() {
+            for &dependency in &self.dependencies[index] {
+                stages[dependency] = stages[dependency].max(stages[index] + 1);
+            }
+        }
+
+        for stage in 0..=stages.iter().copied().max().unwrap_or(0) {
+            let stopping = (0..self.tasks.len())
+                .filter(|&index| stages[index] == stage)
+                .map(|index| {
+                    let state = &mut self.states[index];
+                    if let Some(probe) = state.probe.take() {
+                        probe.abort();
+                    }
+                    if let Some(preparing) = state.preparing.take() {
+                        preparing.abort();
+                    }
+                    if matches!(
+                        state.status,
+                        Status::Pending | Status::Running | Status::Restarting
+                    ) {
+                        state.status = Status::Stopped;
+                        state.exit_status = None;
+                    }
+                    stop(&self.tasks[index], state.pid.take(), state.handle.take())
+                })
+                .collect::<Vec<_>>();
+            future::join_all(stopping).await;
+        }
+    }
+}
+
+async fn next_keys(keyboard: &mut Option<Keyboard>) -> Vec<u8> {
+    match keyboard {
+        Some(keyboard) => keyboard.recv().await,
+        None => future::pending().await,
+    }
+}
+
+async fn stop(task: &Task, pid: Option<u32>, handle: Option<JoinHandle<()>>) {
+    let Some(mut handle) = handle else {
+        return;
+    };
+
+    if let Some(pid) = pid {
+        task.print(
+            format!("stopping with {}", task.stop_signal.as_str())
+                .bold()
+                .white(),
+        );
+        if killpg(Pid::from_raw(pid as i32), task.stop_signal).is_ok() {
+            if time::timeout(task.stop_timeout, &mut handle).await.is_ok() {
+                return;
+            }
+            task.print(
+                format!(
+                    "did not stop within {:.2}s, killing",
+                    task.stop_timeout.as_secs_f32()
+                )
+                .bold()
+                .yellow(),
+            );
+        }
+    }
+
+    handle.abort();
+    let _ = handle.await;
+}
@@ -0,0 +1,391 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    os::unix::net,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use clap::Subcommand;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{unix::OwnedWriteHalf, UnixListener, UnixStream},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::UnboundedSender,
+        oneshot,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    check::did_you_mean,
+    config::parse_signal,
+    output::{self, Output, Phase},
+    runner::Control,
+    summary::{self, TaskStatus},
+};
+
+/// Where a session's control socket goes, relative to the directory of its `tasks.toml`.
+const SOCKET: &str = ".concurrently/control.sock";
+
+/// Lines of output kept across all tasks, for `logs`.
+const HISTORY: usize = 10_000;
+
+/// Lines sent for `logs` when `tail` isn't given.
+const DEFAULT_TAIL: usize = 100;
+
+pub fn path(root: &Path) -> PathBuf {
+    root.join(SOCKET)
+}
+
+/// A request sent to the control socket as a line of JSON, tagged by `command`.
+#[derive(Subcommand, Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// List each task's status, pid, restarts and uptime
+    List,
+    /// Restart a task, stopping it with its stop signal first
+    Restart { task: String },
+    /// Stop a task, which stays stopped until it's started again
+    Stop { task: String },
+    /// Start a task again once it has exited or been stopped
+    Start { task: String },
+    /// Send a signal to a task's processes, such as HUP or SIGUSR1
+    Signal { task: String, signal: String },
+    /// Print a task's recent output, or every task's if none is given
+    Logs {
+        task: Option<String>,
+        /// Number of lines to print
+        #[clap(long, short = 'n', default_value_t = DEFAULT_TAIL)]
+        #[serde(default = "default_tail")]
+        tail: usize,
+        /// Keep printing output as it comes in
+        #[clap(long, short)]
+        #[serde(default)]
+        follow: bool,
+    },
+}
+
+fn default_tail() -> usize {
+    DEFAULT_TAIL
+}
+
+/// Reply to a request, sent once it has been handled.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tasks: Option<Vec<TaskInfo>>,
+}
+
+/// Status of a task as listed by `list`.
+#[derive(Serialize, Deserialize)]
+pub struct TaskInfo {
+    pub name: String,
+    pub status: Phase,
+    pub pid: Option<u32>,
+    pub restarts: usize,
+    /// Time spent running in seconds, across all runs.
+    pub uptime: f64,
+}
+
+impl From<&TaskStatus<'_>> for TaskInfo {
+    fn from(status: &TaskStatus) -> Self {
+        TaskInfo {
+            name: status.name.to_string(),
+            status: status.phase,
+            pid: status.pid,
+            restarts: status.restarts,
+            uptime: status.uptime.as_secs_f64(),
+        }
+    }
+}
+
+/// A line of a task's output without escape sequences, sent for `logs`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogLine {
+    pub task: String,
+    pub line: String,
+}
+
+/// Anything the control socket sends back.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Message {
+    Log(LogLine),
+    Response(Response),
+}
+
+/// Listens on the control socket for as long as it's kept, removing the socket once dropped.
+pub struct Server {
+    path: PathBuf,
+    handle: JoinHandle<()>,
+}
+
+impl Server {
+    /// Handles requests for the given tasks by sending controls to the runner.
+    pub fn start(
+        path: PathBuf,
+        names: Vec<String>,
+        controls: UnboundedSender<Control>,
+    ) -> anyhow::Result<Self> {
+        if path.exists() {
+            if net::UnixStream::connect(&path).is_ok() {
+                bail!("another session is already listening on {}", path.display());
+            }
+            // Left behind by a session which didn't shut down cleanly.
+            let _ = fs::remove_file(&path);
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to listen on {}", path.display()))?;
+
+        let logs = Logs::start();
+        let names: Arc<[String]> = names.into();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let names = names.clone();
+                let controls = controls.clone();
+                let logs = logs.clone();
+                tokio::spawn(async move {
+                    let _ = serve(stream, &names, &controls, &logs).await;
+                });
+            }
+        });
+
+        Ok(Server { path, handle })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.handle.abort();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Recent output of every task, and a channel for following new output.
+#[derive(Clone)]
+struct Logs {
+    history: Arc<Mutex<VecDeque<LogLine>>>,
+    tx: broadcast::Sender<LogLine>,
+}
+
+impl Logs {
+    fn start() -> Self {
+        let (tx, _) = broadcast::channel(1024);
+        let logs = Logs {
+            history: Arc::new(Mutex::new(VecDeque::new())),
+            tx,
+        };
+
+        let mut output = output::tap();
+        let tapped = logs.clone();
+        tokio::spawn(async move {
+            while let Some(output) = output.recv().await {
+                if let Output::Line { task, line } = output {
+                    let line = LogLine {
+                        task,
+                        line: output::strip_ansi(&line),
+                    };
+                    // Sent while holding the history, so followers don't miss or repeat lines.
+                    let mut history = tapped.history.lock().unwrap();
+                    if history.len() == HISTORY {
+                        history.pop_front();
+                    }
+                    history.push_back(line.clone());
+                    let _ = tapped.tx.send(line);
+                }
+            }
+        });
+
+        logs
+    }
+}
+
+async fn serve(
+    stream: UnixStream,
+    names: &[String],
+    controls: &UnboundedSender<Control>,
+    logs: &Logs,
+) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match serde_json::from_str(&line) {
+            Ok(Request::Logs { task, tail, follow }) => {
+                match task.map(|name| find(&name, names)).transpose() {
+                    Ok(task) => {
+                        let task = task.map(|index| names[index].as_str());
+                        send_logs(&mut write, logs, task, tail, follow).await?;
+                        Response {
+                            ok: true,
+                            ..Response::default()
+                        }
+                    }
+                    Err(err) => error(err),
+                }
+            }
+            Ok(request) => handle(request, names, controls).await.unwrap_or_else(error),
+            Err(err) => error(format!("invalid request: {err}")),
+        };
+        send(&mut write, &response).await?;
+    }
+
+    Ok(())
+}
+
+async fn handle(
+    request: Request,
+    names: &[String],
+    controls: &UnboundedSender<Control>,
+) -> Result<Response, String> {
+    let control = match request {
+        Request::List => {
+            let (tx, rx) = oneshot::channel();
+            let _ = controls.send(Control::Status(tx));
+            let tasks = rx
+                .await
+                .map_err(|_| "the session is shutting down".to_string())?;
+            return Ok(Response {
+                ok: true,
+                tasks: Some(tasks),
+                ..Response::default()
+            });
+        }
+        Request::Restart { task } => Control::Restart(find(&task, names)?),
+        Request::Stop { task } => Control::Stop(find(&task, names)?),
+        Request::Start { task } => Control::Start(find(&task, names)?),
+        Request::Signal { task, signal } => {
+            let index = find(&task, names)?;
+            let signal = parse_signal(&signal).ok_or_else(|| format!("unknown signal {signal}"))?;
+            Control::Signal(index, signal)
+        }
+        Request::Logs { .. } => unreachable!("logs are sent by the connection"),
+    };
+    controls
+        .send(control)
+        .map_err(|_| "the session is shutting down".to_string())?;
+
+    Ok(Response {
+        ok: true,
+        ..Response::default()
+    })
+}
+
+/// Sends the last `tail` lines of output, then new output as it comes in if `follow` is set until
+/// the session ends.
+async fn send_logs(
+    write: &mut OwnedWriteHalf,
+    logs: &Logs,
+    task: Option<&str>,
+    tail: usize,
+    follow: bool,
+) -> io::Result<()> {
+    let matches = |line: &LogLine| task.is_none_or(|task| line.task == task);
+    let (backlog, mut rx) = {
+        let history = logs.history.lock().unwrap();
+        let mut backlog: Vec<_> = history
+            .iter()
+            .rev()
+            .filter(|line| matches(line))
+            .take(tail)
+            .cloned()
+            .collect();
+        backlog.reverse();
+        (backlog, logs.tx.subscribe())
+    };
+
+    for line in &backlog {
+        send(write, line).await?;
+    }
+    if !follow {
+        return Ok(());
+    }
+    loop {
+        match rx.recv().await {
+            Ok(line) if matches(&line) => send(write, &line).await?,
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn send(write: &mut OwnedWriteHalf, message: &impl Serialize) -> io::Result<()> {
+    let mut json = serde_json::to_vec(message)?;
+    json.push(b'\n');
+    write.write_all(&json).await
+}
+
+fn find(name: &str, names: &[String]) -> Result<usize, String> {
+    names.iter().position(|n| n == name).ok_or_else(|| {
+        let mut err = format!("unknown task {name}");
+        if let Some(suggestion) = did_you_mean(name, names.iter().map(String::as_str)) {
+            err.push_str(", ");
+            err.push_str(&suggestion);
+        }
+        err
+    })
+}
+
+fn error(error: String) -> Response {
+    Response {
+        ok: false,
+        error: Some(error),
+        ..Response::default()
+    }
+}
+
+/// Sends a request to the session listening on `path`, printing what it sends back.
+pub async fn ctl(path: &Path, request: Request) -> anyhow::Result<()> {
+    let stream = UnixStream::connect(path).await.with_context(|| {
+        format!(
+            "no running session found, could not connect to {}",
+            path.display()
+        )
+    })?;
+    let (read, mut write) = stream.into_split();
+    send(&mut write, &request).await?;
+
+    let all_tasks = matches!(request, Request::Logs { task: None, .. });
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let message = serde_json::from_str(&line)
+            .with_context(|| format!("unexpected reply from the session: {line}"))?;
+        match message {
+            Message::Log(log) if all_tasks => println!("{} | {}", log.task.bold(), log.line),
+            Message::Log(log) => println!("{}", log.line),
+            Message::Response(response) => {
+                if let Some(error) = response.error {
+                    bail!(error);
+                }
+                if let Some(tasks) = response.tasks {
+                    let statuses: Vec<_> = tasks
+                        .iter()
+                        .map(|task| TaskStatus {
+                            name: &task.name,
+                            phase: task.status,
+                            pid: task.pid,
+                            restarts: task.restarts,
+                            uptime: Duration::from_secs_f64(task.uptime),
+                        })
+                        .collect();
+                    summary::print_status(&statuses);
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus, time::Duration};
+
+use colored::{ColoredString, Colorize};
+use nix::sys::signal::Signal;
+
+use crate::output::{self, Phase};
+
+/// How a task ended up once the run is over.
+#[derive(Clone, Copy)]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+    Stopped,
+    Skipped,
+}
+
+/// A row of the summary printed at the end of a run.
+pub struct TaskSummary<'a> {
+    pub name: &'a str,
+    pub outcome: Outcome,
+    pub exit_status: Option<ExitStatus>,
+    pub restarts: usize,
+    pub uptime: Duration,
+    /// How long preparing the task took, if it had anything to prepare.
+    pub prepare: Option<Duration>,
+}
+
+/// A row of the status listed while tasks are running.
+pub struct TaskStatus<'a> {
+    pub name: &'a str,
+    pub phase: Phase,
+    pub pid: Option<u32>,
+    pub restarts: usize,
+    pub uptime: Duration,
+}
+
+const HEADERS: [&str; 6] = ["task", "status", "exit", "restarts", "uptime", "prepare"];
+
+const STATUS_HEADERS: [&str; 5] = ["task", "status", "pid", "restarts", "uptime"];
+
+pub fn print(summaries: &[TaskSummary]) {
+    let rows: Vec<[String; 6]> = summaries
+        .iter()
+        .map(|summary| {
+            [
+                summary.name.to_string(),
+                outcome(summary.outcome).to_string(),
+                summary.exit_status.map(exit).unwrap_or_else(|| "-".into()),
+                summary.restarts.to_string(),
+                duration(summary.uptime),
+                summary.prepare.map(duration).unwrap_or_else(|| "-".into()),
+            ]
+        })
+        .collect();
+
+    println!();
+    let lines = table(HEADERS, &rows, |row, column, cell| match column {
+        0 => cell.bold(),
+        1 => color(summaries[row].outcome, cell),
+        _ => cell.normal(),
+    });
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+/// Lists the status of each task as a message, so it goes wherever output is going.
+pub fn print_status(statuses: &[TaskStatus]) {
+    let rows: Vec<[String; 5]> = statuses
+        .iter()
+        .map(|status| {
+            [
+                status.name.to_string(),
+                status.phase.as_str().to_string(),
+                status.pid.map_or_else(|| "-".into(), |pid| pid.to_string()),
+                status.restarts.to_string(),
+                duration(status.uptime),
+            ]
+        })
+        .collect();
+
+    let lines = table(STATUS_HEADERS, &rows, |row, column, cell| match column {
+        0 => cell.bold(),
+        1 => phase_color(statuses[row].phase, cell),
+        _ => cell.normal(),
+    });
+    for line in lines {
+        output::message(line);
+    }
+}
+
+/// Lines of a table with each column padded to its widest cell, styling cells by row and column.
+fn table<const N: usize>(
+    headers: [&str; N],
+    rows: &[[String; N]],
+    style: impl Fn(usize, usize, String) -> ColoredString,
+) -> Vec<String> {
+    let mut widths = headers.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header: Vec<_> = headers
+        .iter()
+        .zip(widths)
+        .map(|(header, width)| format!("{header:width$}"))
+        .collect();
+    let mut lines = vec![header.join("  ").trim_end().bold().white().to_string()];
+    for (index, row) in rows.iter().enumerate() {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                style(index, column, format!("{cell:width$}")).to_string()
+            })
+            .collect();
+        lines.push(cells.join("  ").trim_end().to_string());
+    }
+    lines
+}
+
+fn outcome(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Succeeded => "succeeded",
+        Outcome::Failed => "failed",
+        Outcome::Stopped => "stopped",
+        Outcome::Skipped => "skipped",
+    }
+}
+
+fn color(outcome: Outcome, cell: String) -> ColoredString {
+    match outcome {
+        Outcome::Succeeded => cell.green(),
+        Outcome::Failed => cell.red(),
+        Outcome::Stopped => cell.yellow(),
+        Outcome::Skipped => cell.dimmed(),
+    }
+}
+
+fn phase_color(phase: Phase, cell: String) -> ColoredString {
+    match phase {
+        Phase::Running => cell.green(),
+        Phase::Starting | Phase::Restarting => cell.yellow(),
+        Phase::Failed => cell.red(),
+        Phase::Exited => cell.blue(),
+        Phase::Pending | Phase::Stopped | Phase::Skipped => cell.dimmed(),
+    }
+}
+
+fn exit(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("code {code}"),
+        (None, Some(signal)) => match Signal::try_from(signal) {
+            Ok(signal) => signal.as_str().to_string(),
+            Err(_) => format!("signal {signal}"),
+        },
+        (None, None) => "-".to_string(),
+    }
+}
+
+pub fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{:.2}s", duration.as_secs_f32()),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60),
+    }
+}
@@ -1,69 +1,143 @@
 use std::{
-    env,
-    path::PathBuf,
-    process::{ExitStatus, Stdio},
-    time::Duration,
+    collections::{BTreeMap, VecDeque},
+    env, fmt, fs,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{self, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
 };
 
 use colored::{Color, Colorize};
 use indicatif::ProgressBar;
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::{self, Pid},
+};
 use tokio::{
-    io::{self, AsyncBufReadExt, BufReader},
-    process::Command,
+    io::{self, AsyncRead, AsyncWriteExt},
+    process::{ChildStdin, Command},
+    sync::mpsc,
     time,
 };
 
 use crate::{
-    config::{self, CargoTaskOptions, ShellTaskOptions, TaskOptions, TaskTypeOptions},
-    log::warn,
+    config::{
+        self, Backoff, CargoTaskOptions, OutputMode, ReadyOptions, RestartPolicy, ShellTaskOptions,
+        Stdin, TaskOptions, TaskTypeOptions,
+    },
+    events::{self, Event, Stream},
+    fake_tty,
+    forward::{Forwarder, Line},
+    log_file::Log,
+    output,
+    prefix::Prefix,
+    project::Project,
+    runner::Notifier,
 };
 
 #[derive(Clone)]
 pub struct Task {
     pub name: String,
     prepare: Option<config::Command>,
-    pub retries: usize,
     pub max_retries: usize,
+    pub depends_on: Vec<String>,
+    pub ready: Option<ReadyOptions>,
+    pub restart: RestartPolicy,
+    pub backoff: Backoff,
+    pub stop_signal: Signal,
+    pub stop_timeout: Duration,
+    pub watch: Vec<String>,
+    pub ignore: Vec<String>,
+    pub watch_gitignore: bool,
+    pub stdin: Stdin,
+    output: OutputMode,
+    log: Option<Log>,
+    pub env: BTreeMap<String, String>,
+    pub cwd: PathBuf,
+    root: PathBuf,
+    target_dir: Option<PathBuf>,
     delay: Option<Duration>,
-    pub tag: String,
+    pub prefix: Prefix,
+    pub color: Color,
     opts: TaskTypeOptions,
     current_exe: PathBuf,
 }
 
 impl Task {
-    pub fn from_options(name: String, color: Color, tag_padding: usize, opts: TaskOptions) -> Self {
-        let mut tag = name.bold().color(color).to_string();
-        tag.push_str(&" ".repeat(tag_padding + 2));
-        tag.push_str(&"|".bold().color(color).to_string());
-        tag.push_str(" ");
-
-        if let TaskTypeOptions::Shell(ShellTaskOptions { command }) = &opts.task_options {
-            if command.is_empty() {
-                warn(format!("task {name} has no command configured"));
-            }
+    pub fn from_options(
+        name: String,
+        color: Color,
+        prefix: Prefix,
+        opts: TaskOptions,
+        env: BTreeMap<String, String>,
+        project: &Project,
+    ) -> Self {
+        let cwd = match &opts.cwd {
+            Some(cwd) => project.resolve(cwd),
+            None => project.root.clone(),
+        };
+
+        let mut watch = opts.watch;
+        if let TaskTypeOptions::Cargo(CargoTaskOptions { rebuild: true, .. }) = &opts.task_options {
+            watch.extend(project.cargo_sources(&name));
         }
 
         let current_exe =
             env::current_exe().expect("could not get path to currently running executable");
 
+        let log = opts.log_file.map(|log_file| {
+            let path = project.resolve(Path::new(&log_file.path.replace("{name}", &name)));
+            Log::start(&name, path, log_file)
+        });
+
         Task {
             name,
             prepare: opts.prepare,
-            retries: 0,
             max_retries: opts.retries,
+            depends_on: opts.depends_on,
+            ready: opts.ready,
+            restart: opts.restart,
+            backoff: opts.backoff,
+            stop_signal: opts.stop_signal,
+            stop_timeout: opts.stop_timeout,
+            watch,
+            ignore: opts.ignore,
+            watch_gitignore: opts.watch_gitignore,
+            stdin: match opts.stdin {
+                Stdin::File(path) => Stdin::File(project.resolve(&path)),
+                stdin => stdin,
+            },
+            output: opts.output.unwrap_or_default(),
+            log,
+            env,
+            cwd,
+            root: project.root.clone(),
+            target_dir: project.target_dir.clone(),
             delay: opts.delay,
-            tag,
+            prefix,
+            color,
             opts: opts.task_options,
             current_exe,
         }
     }
 
-    pub async fn prepare(&self, pb: ProgressBar) -> Option<io::Result<ExitStatus>> {
+    /// Whether the task is built or has a `prepare` command before it runs.
+    pub fn has_prepare(&self) -> bool {
+        matches!(self.opts, TaskTypeOptions::Cargo(_)) || self.prepare.is_some()
+    }
+
+    /// Builds cargo tasks and runs the `prepare` command, showing their output on `pb` if given
+    /// rather than printing it.
+    pub async fn prepare(&self, pb: Option<ProgressBar>) -> Option<io::Result<ExitStatus>> {
         let result = match &self.opts {
             TaskTypeOptions::Shell(_) => None,
-            TaskTypeOptions::Cargo(CargoTaskOptions { release, features }) => {
+            TaskTypeOptions::Cargo(CargoTaskOptions {
+                release, features, ..
+            }) => {
                 // Build the project
                 let mut cmd = self.new_command();
+                cmd.current_dir(&self.root);
                 cmd.arg("cargo")
                     .arg("build")
                     .arg("-p")
@@ -77,7 +151,7 @@ impl Task {
                     cmd.arg(features.join(","));
                 }
 
-                let status = match exec(cmd, &self.tag, Some(pb.clone())).await {
+                let status = match exec(cmd, self, pb.clone(), None).await {
                     Ok(status) => status,
                     Err(err) => return Some(Err(err)),
                 };
@@ -93,18 +167,16 @@ impl Task {
             let mut cmd = self.new_command();
             cmd.arg("sh").arg("-c").arg(prepare.to_string());
 
-            let status = match exec(cmd, &self.tag, Some(pb)).await {
+            let status = match exec(cmd, self, pb, None).await {
                 Ok(status) => status,
                 Err(err) => return Some(Err(err)),
             };
 
             if !status.success() {
-                println!(
-                    "{} {}",
-                    self.tag,
+                self.print(
                     format!("process exited with status code {status}")
                         .bold()
-                        .red()
+                        .red(),
                 );
             }
 
@@ -114,75 +186,154 @@ impl Task {
         }
     }
 
-    pub async fn run(&self) -> io::Result<ExitStatus> {
+    pub async fn run(&self, notifier: &Notifier) -> io::Result<ExitStatus> {
         self.sleep().await;
 
-        let cmd = match &self.opts {
+        let mut cmd = self.new_std_command();
+        match &self.stdin {
+            Stdin::Null => {
+                cmd.stdin(Stdio::null());
+            }
+            Stdin::Inherit => {
+                cmd.arg(fake_tty::PTY_STDIN).stdin(Stdio::piped());
+            }
+            Stdin::File(path) => {
+                let file = fs::File::open(path).map_err(|err| {
+                    io::Error::new(
+                        err.kind(),
+                        format!("failed to open stdin {}: {err}", path.display()),
+                    )
+                })?;
+                cmd.stdin(file);
+            }
+        }
+        match &self.opts {
             TaskTypeOptions::Shell(ShellTaskOptions { command }) => {
-                let mut cmd = self.new_command();
                 cmd.arg("sh").arg("-c").arg(command.to_string());
-                cmd
             }
             TaskTypeOptions::Cargo(CargoTaskOptions { release, .. }) => {
-                let mut cmd = self.new_command();
-                cmd.arg(format!(
-                    "./target/{}/{}",
-                    if *release { "release" } else { "debug" },
-                    &self.name
-                ));
-                cmd
+                let target_dir = self
+                    .target_dir
+                    .clone()
+                    .unwrap_or_else(|| self.root.join("target"));
+                cmd.arg(
+                    target_dir
+                        .join(if *release { "release" } else { "debug" })
+                        .join(&self.name),
+                );
             }
-        };
-
-        let status = exec(cmd, &self.tag, None).await?;
+        }
+        let status = exec(cmd.into(), self, None, Some(notifier)).await?;
+        events::emit(Event::Exit {
+            task: &self.name,
+            exit: status.into(),
+        });
 
         if status.success() {
-            println!(
-                "{} {}",
-                self.tag,
+            self.print(
                 format!("process exited with status code {status}")
                     .bold()
-                    .white()
+                    .white(),
             );
         } else {
-            println!(
-                "{} {}",
-                self.tag,
+            self.print(
                 format!("process exited with status code {status}")
                     .bold()
-                    .red()
+                    .red(),
             );
         }
 
         Ok(status)
     }
 
+    /// Prints a line of output or a message about the task, after its tag.
+    pub fn print(&self, line: impl fmt::Display) {
+        output::task(&self.name, &self.tag(), line);
+    }
+
+    /// Prefix for the task's lines, rendered for its current run.
+    pub fn tag(&self) -> String {
+        self.prefix.render()
+    }
+
     fn new_command(&self) -> Command {
-        let mut cmd = Command::new(&self.current_exe);
+        self.new_std_command().into()
+    }
+
+    fn new_std_command(&self) -> process::Command {
+        let mut cmd = process::Command::new(&self.current_exe);
         cmd.arg("--fake-tty");
-        cmd.envs(env::vars());
+        cmd.current_dir(&self.cwd);
+        // Run the command in a session and process group of its own, which it's signalled and
+        // killed through, and which keeps Ctrl-C in the terminal from reaching it directly.
+        unsafe {
+            cmd.pre_exec(|| unistd::setsid().map(drop).map_err(Into::into));
+        }
+        cmd.env_clear();
+        cmd.envs(&self.env);
         cmd
     }
 
     async fn sleep(&self) {
         if let Some(delay) = self.delay {
-            println!(
-                "{} {}",
-                self.tag,
+            self.print(
                 format!("waiting {:.2}s", delay.as_secs_f32())
                     .bold()
-                    .white()
+                    .white(),
             );
             time::sleep(delay).await;
         }
     }
 }
 
-async fn exec(mut cmd: Command, tag: &str, pb: Option<ProgressBar>) -> io::Result<ExitStatus> {
+/// A task's process group, which is killed when dropped so that nothing the task started outlives
+/// it, whether it exited by itself or was aborted.
+struct ProcessGroup(Pid);
+
+impl ProcessGroup {
+    fn new(pid: u32) -> Self {
+        ProcessGroup(Pid::from_raw(pid as i32))
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        let _ = killpg(self.0, Signal::SIGKILL);
+    }
+}
+
+/// Writes keyboard input sent to the returned channel into the task's stdin.
+fn forward_input(mut stdin: ChildStdin) -> mpsc::UnboundedSender<Vec<u8>> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
+        while let Some(bytes) = rx.recv().await {
+            if stdin.write_all(&bytes).await.is_err() {
+                break;
+            }
+        }
+    });
+    tx
+}
+
+async fn exec(
+    mut cmd: Command,
+    task: &Task,
+    pb: Option<ProgressBar>,
+    notifier: Option<&Notifier>,
+) -> io::Result<ExitStatus> {
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
 
     let mut child = cmd.kill_on_drop(true).spawn()?;
+    let _group = child.id().map(ProcessGroup::new);
+    if let Some(notifier) = notifier {
+        task.prefix.spawned(child.id());
+        events::emit(Event::Spawn {
+            task: &task.name,
+            pid: child.id(),
+        });
+        notifier.spawned(child.id(), child.stdin.take().map(forward_input));
+    }
 
     let stdout = child
         .stdout
@@ -193,58 +344,32 @@ async fn exec(mut cmd: Command, tag: &str, pb: Option<ProgressBar>) -> io::Resul
         .take()
         .expect("child did not have a handle to stderr");
 
-    let mut stdout_reader = BufReader::new(stdout).lines();
-    let mut stderr_reader = BufReader::new(stderr).lines();
-
-    let stdout_task = {
-        let tag = tag.to_string();
-        let pb = pb.clone();
-        tokio::spawn(async move {
-            while let Some(line) = stdout_reader.next_line().await.unwrap() {
-                if !line.trim().is_empty() {
-                    let parts = line.split('\r');
-                    let line = parts.last().unwrap_or(&line);
-                    if let Some(pb) = &pb {
-                        pb.set_message(line.to_string());
-                    } else {
-                        println!("{tag} {}", line);
-                    }
-                }
-            }
-        })
-    };
-
-    let stderr_task = {
-        let tag = tag.to_string();
-        let pb = pb.clone();
-        tokio::spawn(async move {
-            let mut last_ten_lines = Vec::with_capacity(20);
-            while let Some(line) = stderr_reader.next_line().await.unwrap() {
-                if !line.trim().is_empty() {
-                    let parts = line.split('\r');
-                    let line = parts.last().unwrap_or(&line);
-                    if let Some(pb) = &pb {
-                        pb.set_message(line.to_string());
-                    } else {
-                        println!("{tag} {}", line);
-                    }
-                    if last_ten_lines.len() >= 20 {
-                        last_ten_lines.remove(0);
-                    }
-                    last_ten_lines.push(line.to_string());
-                }
-            }
-            last_ten_lines
-        })
-    };
+    let printer = Printer::new(task);
+    let stdout_task = tokio::spawn(forward(
+        Forwarder::new(stdout),
+        Stream::Stdout,
+        printer.clone(),
+        task.log.clone(),
+        pb.clone(),
+        notifier.cloned(),
+    ));
+    let stderr_task = tokio::spawn(forward(
+        Forwarder::new(stderr),
+        Stream::Stderr,
+        printer.clone(),
+        task.log.clone(),
+        pb.clone(),
+        notifier.cloned(),
+    ));
 
     let status = child
         .wait()
         .await
         .expect("child process encountered an error");
 
-    let _ = stdout_task.await.unwrap();
-    let last_ten_lines = stderr_task.await.unwrap();
+    stdout_task.await.unwrap();
+    let last_lines = stderr_task.await.unwrap();
+    drop(printer);
 
     if !status.success() {
         if let Some(pb) = pb {
@@ -254,7 +379,7 @@ async fn exec(mut cmd: Command, tag: &str, pb: Option<ProgressBar>) -> io::Resul
                     .white()
                     .to_string(),
             );
-            for line in last_ten_lines {
+            for line in last_lines {
                 pb.println(line);
             }
         }
@@ -262,3 +387,110 @@ async fn exec(mut cmd: Command, tag: &str, pb: Option<ProgressBar>) -> io::Resul
 
     Ok(status)
 }
+
+/// Prints one of a run's output streams as it comes in and saves it to the task's log file,
+/// returning its last lines.
+async fn forward(
+    mut lines: Forwarder<impl AsyncRead + Unpin>,
+    stream: Stream,
+    printer: Printer,
+    log: Option<Log>,
+    pb: Option<ProgressBar>,
+    notifier: Option<Notifier>,
+) -> VecDeque<String> {
+    let mut last_lines = VecDeque::with_capacity(20);
+    while let Some(line) = lines.next().await.unwrap() {
+        let (Line::Done(text) | Line::Partial(text)) = &line;
+        if events::enabled() {
+            if let Line::Done(text) = &line {
+                events::emit(Event::Output {
+                    task: &printer.group.name,
+                    stream,
+                    line: output::strip_ansi(text),
+                    time: events::time(SystemTime::now()),
+                });
+            }
+        } else if let Some(pb) = &pb {
+            pb.set_message(text.clone());
+        } else {
+            match &line {
+                Line::Done(text) => printer.print(text),
+                Line::Partial(text) => printer.partial(text),
+            }
+        }
+        if let Some(notifier) = &notifier {
+            notifier.line(text);
+        }
+        if let Line::Done(text) = line {
+            if let Some(log) = &log {
+                log.write(&text);
+            }
+            if last_lines.len() == 20 {
+                last_lines.pop_front();
+            }
+            last_lines.push_back(text);
+        }
+    }
+    last_lines
+}
+
+/// Prints a run's output lines according to the task's output mode.
+///
+/// Grouped output is held until the last clone is dropped, which also happens if the run is
+/// aborted, and then printed together.
+#[derive(Clone)]
+struct Printer {
+    mode: OutputMode,
+    group: Arc<Group>,
+}
+
+struct Group {
+    name: String,
+    prefix: Prefix,
+    lines: Mutex<Vec<String>>,
+}
+
+impl Printer {
+    fn new(task: &Task) -> Self {
+        Printer {
+            mode: task.output,
+            group: Arc::new(Group {
+                name: task.name.clone(),
+                prefix: task.prefix.clone(),
+                lines: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    fn print(&self, line: &str) {
+        let Group {
+            name,
+            prefix,
+            lines,
+        } = &*self.group;
+        match self.mode {
+            OutputMode::Prefixed | OutputMode::Json => output::task(name, &prefix.render(), line),
+            OutputMode::Grouped => lines.lock().unwrap().push(line.to_string()),
+            OutputMode::Raw => output::raw(name, line),
+        }
+    }
+
+    /// Shows a line which isn't finished yet, which grouped output leaves for the finished line.
+    fn partial(&self, line: &str) {
+        let Group { name, prefix, .. } = &*self.group;
+        match self.mode {
+            OutputMode::Prefixed => output::partial(name, Some(&prefix.render()), line),
+            OutputMode::Grouped | OutputMode::Json => {}
+            OutputMode::Raw => output::partial(name, None, line),
+        }
+    }
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        let lines = self.lines.get_mut().unwrap();
+        if !lines.is_empty() {
+            output::group(&self.name, &self.prefix.render(), lines);
+        }
+    }
+}
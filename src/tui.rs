@@ -0,0 +1,540 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use ansi_to_tui::IntoText;
+use anyhow::Context;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use tokio::{
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::JoinHandle,
+    time,
+};
+
+use crate::{
+    config::Stdin,
+    output::{self, Output, Phase},
+    prefix::Prefix,
+    runner::Control,
+    task::Task,
+};
+
+/// Lines of output kept for each pane.
+const SCROLLBACK: usize = 10_000;
+
+/// How often the screen is redrawn while output is coming in.
+const TICK: Duration = Duration::from_millis(50);
+
+/// Full screen view of the running tasks, with a pane of output for each task.
+///
+/// All output is captured while it's open, and the terminal is restored when it's closed.
+pub struct Tui {
+    close: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Tui {
+    /// Takes over the terminal, sending what the user asks for to the runner as controls.
+    pub fn start(tasks: &[Task], controls: UnboundedSender<Control>) -> anyhow::Result<Self> {
+        let terminal = ratatui::try_init().context("failed to set up the terminal")?;
+        let output = output::capture();
+        let app = App::new(tasks, controls);
+
+        let (close, closed) = oneshot::channel();
+        let handle = tokio::spawn(app.run(terminal, output, closed));
+        Ok(Tui { close, handle })
+    }
+
+    /// Closes the TUI and restores the terminal.
+    pub async fn close(self) {
+        let _ = self.close.send(());
+        let _ = self.handle.await;
+    }
+}
+
+struct App {
+    /// Merged output of every task, followed by a pane for each task.
+    panes: Vec<Pane>,
+    /// Pane index of each task by name.
+    indexes: HashMap<String, usize>,
+    selected: usize,
+    mode: Mode,
+    /// Confirmed search, highlighted in every pane.
+    search: Option<String>,
+    /// Latest message from concurrently itself.
+    message: Option<String>,
+    controls: UnboundedSender<Control>,
+    /// Height of the log pane as last drawn, for scrolling a page at a time.
+    height: usize,
+    dirty: bool,
+}
+
+enum Mode {
+    Normal,
+    /// Typing a search.
+    Search(String),
+    /// Sending keys to the selected task.
+    Input,
+}
+
+struct Pane {
+    name: String,
+    /// Shown before the task's lines in the merged pane.
+    prefix: Option<Prefix>,
+    color: Color,
+    phase: Option<Phase>,
+    /// Whether the task takes keyboard input.
+    input: bool,
+    lines: VecDeque<LogLine>,
+    /// Whether the last line is unfinished, so that the next one replaces it.
+    partial: bool,
+    /// Lines hidden below the bottom of the pane, so it stays put while new output comes in.
+    scroll: usize,
+}
+
+struct LogLine {
+    text: Line<'static>,
+    /// Text without styling, for searching.
+    plain: String,
+}
+
+impl App {
+    fn new(tasks: &[Task], controls: UnboundedSender<Control>) -> Self {
+        let mut panes = vec![Pane::new("all".to_string(), None, Color::White, false)];
+        panes.extend(tasks.iter().map(|task| {
+            Pane::new(
+                task.name.clone(),
+                Some(task.prefix.clone()),
+                color(task.color),
+                task.stdin == Stdin::Inherit,
+            )
+        }));
+        let indexes = tasks
+            .iter()
+            .enumerate()
+            .map(|(index, task)| (task.name.clone(), index + 1))
+            .collect();
+
+        App {
+            panes,
+            indexes,
+            selected: 0,
+            mode: Mode::Normal,
+            search: None,
+            message: None,
+            controls,
+            height: 0,
+            dirty: true,
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut terminal: DefaultTerminal,
+        mut output: UnboundedReceiver<Output>,
+        mut closed: oneshot::Receiver<()>,
+    ) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (events, mut events_rx) = mpsc::unbounded_channel();
+        let reader = thread::spawn({
+            let stop = stop.clone();
+            move || read_events(events, &stop)
+        });
+
+        let mut tick = time::interval(TICK);
+        loop {
+            tokio::select! {
+                Some(output) = output.recv() => self.output(output),
+                Some(event) = events_rx.recv() => self.event(event),
+                _ = tick.tick() => {
+                    if self.dirty {
+                        self.dirty = false;
+                        let _ = terminal.draw(|frame| self.draw(frame));
+                    }
+                }
+                _ = &mut closed => break,
+            }
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        let _ = reader.join();
+        output::release();
+        ratatui::restore();
+    }
+
+    fn output(&mut self, output: Output) {
+        self.dirty = true;
+        match output {
+            Output::Line { task, line } => {
+                let Some(&index) = self.indexes.get(&task) else {
+                    return;
+                };
+                let tag = self.panes[index].prefix.as_ref().map(Prefix::render);
+                let tagged = format!("{} {line}", tag.unwrap_or_default());
+                self.panes[index].push(&line);
+                self.panes[0].push(&tagged);
+            }
+            // Left out of the merged pane, where other output may have come after the line.
+            Output::Partial { task, line } => {
+                if let Some(&index) = self.indexes.get(&task) {
+                    self.panes[index].push(&line);
+                    self.panes[index].partial = true;
+                }
+            }
+            Output::Message(line) => {
+                self.panes[0].push(&line);
+                self.message = Some(line);
+            }
+            Output::Phase { task, phase } => {
+                if let Some(&index) = self.indexes.get(&task) {
+                    self.panes[index].phase = Some(phase);
+                }
+            }
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        self.dirty = true;
+        let Event::Key(key) = event else {
+            return;
+        };
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+
+        match &mut self.mode {
+            Mode::Normal => self.normal_key(key),
+            Mode::Search(query) => match key.code {
+                KeyCode::Enter => {
+                    let query = std::mem::take(query);
+                    self.mode = Mode::Normal;
+                    self.search = (!query.is_empty()).then_some(query);
+                    self.next_match(true);
+                }
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Backspace => {
+                    query.pop();
+                }
+                KeyCode::Char(c) => query.push(c),
+                _ => {}
+            },
+            Mode::Input => {
+                if key.code == KeyCode::Char('t') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    self.mode = Mode::Normal;
+                } else if let (Some(task), Some(bytes)) = (self.task(), key_bytes(key)) {
+                    let _ = self.controls.send(Control::Input(task, bytes));
+                }
+            }
+        }
+    }
+
+    fn normal_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let page = self.height.max(1);
+        let pane = &mut self.panes[self.selected];
+        match key.code {
+            KeyCode::Char('c') if ctrl => self.quit(),
+            KeyCode::Char('q') => self.quit(),
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.panes.len() - 1);
+            }
+            KeyCode::PageUp => pane.scroll_to(pane.scroll + page),
+            KeyCode::PageDown => pane.scroll_to(pane.scroll.saturating_sub(page)),
+            KeyCode::Home | KeyCode::Char('g') => pane.scroll_to(usize::MAX),
+            KeyCode::End | KeyCode::Char('G') => pane.scroll_to(0),
+            KeyCode::Char('/') => self.mode = Mode::Search(String::new()),
+            KeyCode::Char('n') => self.next_match(true),
+            KeyCode::Char('N') => self.next_match(false),
+            KeyCode::Esc => self.search = None,
+            KeyCode::Char('r') => {
+                if let Some(task) = self.task() {
+                    let _ = self.controls.send(Control::Restart(task));
+                }
+            }
+            KeyCode::Char('s') => {
+                if let Some(task) = self.task() {
+                    let _ = self.controls.send(Control::Stop(task));
+                }
+            }
+            KeyCode::Char('i') | KeyCode::Enter => {
+                if pane.input {
+                    self.mode = Mode::Input;
+                } else if self.selected > 0 {
+                    self.message = Some(format!("task {} doesn't take input", pane.name));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks the runner to stop, which kills the tasks if they're already stopping.
+    fn quit(&mut self) {
+        let _ = self.controls.send(Control::Quit);
+    }
+
+    /// Index of the selected task, unless the merged pane is selected.
+    fn task(&self) -> Option<usize> {
+        self.selected.checked_sub(1)
+    }
+
+    /// Scrolls the selected pane to the next match above the bottom line, or below it if `up` is
+    /// `false`.
+    fn next_match(&mut self, up: bool) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let pane = &mut self.panes[self.selected];
+        let bottom = pane.lines.len().saturating_sub(pane.scroll + 1);
+        let matches = |(_, line): &(usize, &LogLine)| line.plain.contains(search.as_str());
+        let found = if up {
+            pane.lines
+                .iter()
+                .enumerate()
+                .take(bottom)
+                .rev()
+                .find(matches)
+        } else {
+            pane.lines.iter().enumerate().skip(bottom + 1).find(matches)
+        };
+        match found {
+            Some((index, _)) => {
+                let scroll = pane.lines.len() - 1 - index;
+                pane.scroll_to(scroll);
+            }
+            None => self.message = Some(format!("no more matches for {search}")),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(2)]).areas(frame.area());
+        let sidebar_width = self
+            .panes
+            .iter()
+            .map(|pane| pane.name.len() as u16 + 6)
+            .max()
+            .unwrap_or(0)
+            .max(16);
+        let [sidebar, log] =
+            Layout::horizontal([Constraint::Length(sidebar_width), Constraint::Min(1)]).areas(main);
+
+        self.draw_sidebar(frame, sidebar);
+        self.draw_log(frame, log);
+        self.draw_footer(frame, footer);
+    }
+
+    fn draw_sidebar(&self, frame: &mut Frame, area: Rect) {
+        let items: Vec<_> = self
+            .panes
+            .iter()
+            .map(|pane| {
+                let marker = match pane.phase {
+                    Some(phase) => Span::styled("● ", phase_style(phase)),
+                    None => Span::raw("  "),
+                };
+                let name = Span::styled(pane.name.clone(), Style::new().fg(pane.color).bold());
+                ListItem::new(Line::from(vec![marker, name]))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::new().borders(Borders::RIGHT))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn draw_log(&mut self, frame: &mut Frame, area: Rect) {
+        let [title, body] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(1)]).areas(area);
+        self.height = body.height as usize;
+        let pane = &self.panes[self.selected];
+
+        let mut spans = vec![Span::styled(
+            format!(" {}", pane.name),
+            Style::new().fg(pane.color).bold(),
+        )];
+        if let Some(phase) = pane.phase {
+            spans.push(Span::styled(
+                format!(" {}", phase.as_str()),
+                phase_style(phase),
+            ));
+        }
+        if pane.scroll > 0 {
+            spans.push(Span::raw(format!(" ({} lines below)", pane.scroll)).dim());
+        }
+        if matches!(self.mode, Mode::Input) {
+            spans.push(Span::raw(" (input)").yellow().bold());
+        }
+        frame.render_widget(Line::from(spans), title);
+
+        let end = pane.lines.len() - pane.scroll;
+        let start = end.saturating_sub(self.height);
+        let lines: Vec<_> = pane
+            .lines
+            .range(start..end)
+            .map(|line| match &self.search {
+                Some(search) if line.plain.contains(search.as_str()) => line
+                    .text
+                    .clone()
+                    .patch_style(Style::new().add_modifier(Modifier::REVERSED)),
+                _ => line.text.clone(),
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), body);
+    }
+
+    fn draw_footer(&self, frame: &mut Frame, area: Rect) {
+        let [message, help] =
+            Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(area);
+        if let Some(text) = &self.message {
+            frame.render_widget(Line::raw(text.as_str()).bold(), message);
+        }
+
+        let help_text = match &self.mode {
+            Mode::Normal => {
+                "↑↓ select  PgUp/PgDn scroll  / search  n/N previous/next match  r restart  s stop  \
+                 i input  q quit"
+                    .to_string()
+            }
+            Mode::Search(query) => format!("/{query}"),
+            Mode::Input => "typing goes to the task, Ctrl-T to stop".to_string(),
+        };
+        frame.render_widget(Line::raw(help_text).dim(), help);
+    }
+}
+
+impl Pane {
+    fn new(name: String, prefix: Option<Prefix>, color: Color, input: bool) -> Self {
+        Pane {
+            name,
+            prefix,
+            color,
+            phase: None,
+            input,
+            lines: VecDeque::new(),
+            partial: false,
+            scroll: 0,
+        }
+    }
+
+    fn push(&mut self, line: &str) {
+        let text = line
+            .trim_end_matches('\r')
+            .into_text()
+            .ok()
+            .and_then(|text| text.lines.into_iter().next())
+            .unwrap_or_default();
+        let plain = text
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        if mem::take(&mut self.partial) {
+            self.lines.pop_back();
+            self.lines.push_back(LogLine { text, plain });
+            return;
+        }
+        self.lines.push_back(LogLine { text, plain });
+
+        if self.lines.len() > SCROLLBACK {
+            self.lines.pop_front();
+        } else if self.scroll > 0 {
+            self.scroll += 1;
+        }
+        self.scroll_to(self.scroll);
+    }
+
+    fn scroll_to(&mut self, scroll: usize) {
+        self.scroll = scroll.min(self.lines.len().saturating_sub(1));
+    }
+}
+
+/// Reads terminal events until `stop` is set, polling so that it notices.
+fn read_events(events: UnboundedSender<Event>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        match event::poll(Duration::from_millis(100)) {
+            Ok(true) => {
+                let Ok(event) = event::read() else {
+                    return;
+                };
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+            Ok(false) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// Bytes a terminal would send for a key, for tasks reading from a pty.
+fn key_bytes(key: KeyEvent) -> Option<Vec<u8>> {
+    let bytes = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            vec![(c.to_ascii_lowercase() as u8) & 0x1f]
+        }
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+fn phase_style(phase: Phase) -> Style {
+    match phase {
+        Phase::Running => Style::new().green(),
+        Phase::Starting | Phase::Restarting => Style::new().yellow(),
+        Phase::Failed => Style::new().red(),
+        Phase::Exited => Style::new().blue(),
+        Phase::Pending | Phase::Stopped | Phase::Skipped => Style::new().dark_gray(),
+    }
+}
+
+fn color(color: colored::Color) -> Color {
+    match color {
+        colored::Color::Black => Color::Black,
+        colored::Color::Red => Color::Red,
+        colored::Color::Green => Color::Green,
+        colored::Color::Yellow => Color::Yellow,
+        colored::Color::Blue => Color::Blue,
+        colored::Color::Magenta => Color::Magenta,
+        colored::Color::Cyan => Color::Cyan,
+        colored::Color::White => Color::Gray,
+        colored::Color::BrightBlack => Color::DarkGray,
+        colored::Color::BrightRed => Color::LightRed,
+        colored::Color::BrightGreen => Color::LightGreen,
+        colored::Color::BrightYellow => Color::LightYellow,
+        colored::Color::BrightBlue => Color::LightBlue,
+        colored::Color::BrightMagenta => Color::LightMagenta,
+        colored::Color::BrightCyan => Color::LightCyan,
+        colored::Color::BrightWhite => Color::White,
+        colored::Color::TrueColor { r, g, b } => Color::Rgb(r, g, b),
+    }
+}
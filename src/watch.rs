@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use tokio::{sync::mpsc, time};
+
+use crate::{log::warn, task::Task};
+
+/// How long files must stop changing before the task is restarted, so that saving several files at
+/// once only restarts it once.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches a task's `watch` patterns for as long as it's kept.
+pub struct Watcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl Watcher {
+    /// Calls `on_change` with the first changed file once each burst of changes has settled.
+    pub fn new(task: &Task, on_change: impl Fn(PathBuf) + Send + 'static) -> anyhow::Result<Self> {
+        let cwd = task.cwd.canonicalize().unwrap_or_else(|_| task.cwd.clone());
+        let patterns: Vec<_> = task
+            .watch
+            .iter()
+            .map(|pattern| Pattern::new(&cwd, pattern))
+            .collect();
+        let mut filter = Filter {
+            watch: glob_set(&patterns)?,
+            ignore: ignore_patterns(&cwd, &task.ignore)?,
+            gitignore: task.watch_gitignore,
+            gitignores: HashMap::new(),
+        };
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+                let Ok(event) = result else {
+                    return;
+                };
+                if matches!(event.kind, EventKind::Access(_)) {
+                    return;
+                }
+                for path in event.paths {
+                    if filter.matches(&path) {
+                        let _ = tx.send(path);
+                    }
+                }
+            })?;
+        for base in bases(&patterns) {
+            if !base.exists() {
+                warn(format!(
+                    "nothing to watch at {} for task {}",
+                    base.display(),
+                    task.name
+                ));
+                continue;
+            }
+            // Editors often replace files rather than writing to them, so files are watched
+            // through their directory.
+            let (path, mode) = match base.parent() {
+                Some(parent) if base.is_file() => (parent, RecursiveMode::NonRecursive),
+                _ => (base.as_path(), RecursiveMode::Recursive),
+            };
+            watcher
+                .watch(path, mode)
+                .with_context(|| format!("failed to watch {}", base.display()))?;
+        }
+
+        tokio::spawn(async move {
+            while let Some(path) = rx.recv().await {
+                while let Ok(Some(_)) = time::timeout(DEBOUNCE, rx.recv()).await {}
+                on_change(path);
+            }
+        });
+
+        Ok(Watcher { _watcher: watcher })
+    }
+}
+
+/// Checks that a `watch` glob is valid.
+pub fn check_watch(pattern: &str) -> Result<(), globset::Error> {
+    GlobBuilder::new(pattern).build().map(drop)
+}
+
+/// Checks that an `ignore` pattern is valid.
+pub fn check_ignore(pattern: &str) -> Result<(), ignore::Error> {
+    GitignoreBuilder::new("").add_line(None, pattern).map(drop)
+}
+
+/// A `watch` glob made absolute, split at the directory which needs watching.
+struct Pattern {
+    /// Deepest directory, or the file itself, without any glob characters.
+    base: PathBuf,
+    globs: Vec<String>,
+}
+
+impl Pattern {
+    fn new(cwd: &Path, pattern: &str) -> Self {
+        let mut base = PathBuf::new();
+        let mut rest = Vec::new();
+        for component in normalize(&cwd.join(pattern)).components() {
+            let component = component.as_os_str().to_string_lossy();
+            if rest.is_empty() && !component.contains(['*', '?', '[', '{']) {
+                base.push(component.as_ref());
+            } else {
+                rest.push(component.into_owned());
+            }
+        }
+
+        let escaped = globset::escape(&base.to_string_lossy());
+        let globs = if rest.is_empty() {
+            // A plain path watches everything under it when it's a directory.
+            vec![format!("{escaped}/**"), escaped]
+        } else {
+            vec![format!("{escaped}/{}", rest.join("/"))]
+        };
+        Pattern { base, globs }
+    }
+}
+
+struct Filter {
+    watch: GlobSet,
+    ignore: Gitignore,
+    gitignore: bool,
+    /// `.gitignore` of each directory seen so far, if it has one.
+    gitignores: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl Filter {
+    fn matches(&mut self, path: &Path) -> bool {
+        if !self.watch.is_match(path) || path.components().any(|c| c.as_os_str() == ".git") {
+            return false;
+        }
+        let is_dir = path.is_dir();
+        if path.starts_with(self.ignore.path())
+            && self
+                .ignore
+                .matched_path_or_any_parents(path, is_dir)
+                .is_ignore()
+        {
+            return false;
+        }
+
+        !self.gitignore || !self.is_gitignored(path, is_dir)
+    }
+
+    /// Checks the `.gitignore` files from the path's directory up to the root of its repository,
+    /// where deeper files take priority.
+    fn is_gitignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        for dir in path.ancestors().skip(1) {
+            let gitignore = self.gitignores.entry(dir.to_path_buf()).or_insert_with(|| {
+                let file = dir.join(".gitignore");
+                file.is_file().then(|| Gitignore::new(file).0)
+            });
+            if let Some(gitignore) = gitignore {
+                let matched = gitignore.matched_path_or_any_parents(path, is_dir);
+                if matched.is_ignore() || matched.is_whitelist() {
+                    return matched.is_ignore();
+                }
+            }
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+
+        false
+    }
+}
+
+fn glob_set(patterns: &[Pattern]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in patterns.iter().flat_map(|pattern| &pattern.globs) {
+        let glob = GlobBuilder::new(glob)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("invalid watch pattern {glob}"))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+fn ignore_patterns(cwd: &Path, patterns: &[String]) -> anyhow::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(cwd);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .with_context(|| format!("invalid ignore pattern {pattern}"))?;
+    }
+    Ok(builder.build()?)
+}
+
+/// Directories to watch, leaving out any inside another.
+fn bases(patterns: &[Pattern]) -> Vec<PathBuf> {
+    let mut bases: Vec<_> = patterns.iter().map(|pattern| &pattern.base).collect();
+    bases.sort();
+    bases.dedup();
+    bases
+        .iter()
+        .filter(|base| {
+            !bases
+                .iter()
+                .any(|other| other != *base && base.starts_with(other))
+        })
+        .map(|base| base.to_path_buf())
+        .collect()
+}
+
+/// Resolves `.` and `..` without touching the filesystem, so patterns can reach outside the
+/// working directory.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
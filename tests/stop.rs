@@ -0,0 +1,70 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::{self, Command},
+    thread,
+    time::Duration,
+};
+
+/// A directory of its own for each test, removed once it's done.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("concurrently-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Whether a process is still running, rather than gone or a zombie waiting to be reaped.
+fn is_running(pid: &str) -> bool {
+    fs::read_to_string(format!("/proc/{pid}/stat"))
+        .is_ok_and(|stat| stat.rsplit(')').next().unwrap().split_whitespace().next() != Some("Z"))
+}
+
+#[test]
+fn kills_processes_which_ignore_the_stop_signal() {
+    let dir = TempDir::new("stop");
+    fs::write(
+        dir.0.join("tasks.toml"),
+        r#"
+kill_others = true
+
+[tasks.stubborn]
+command = "trap '' TERM HUP; sleep 1000 & echo $! > sleep.pid; wait"
+stop_timeout = "500ms"
+
+[tasks.quick]
+command = "sleep 1"
+"#,
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_concurrently"))
+        .arg("--config")
+        .arg(dir.0.join("tasks.toml"))
+        .current_dir(&dir.0)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+
+    let pid = fs::read_to_string(dir.0.join("sleep.pid")).unwrap();
+    let pid = pid.trim();
+    // The kill is asynchronous, so give it a moment to land.
+    for _ in 0..50 {
+        if !is_running(pid) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("sleep {pid} outlived its task");
+}